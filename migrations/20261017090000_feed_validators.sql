@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS feed_validators (
+    feed_url TEXT PRIMARY KEY NOT NULL,
+    etag TEXT,
+    last_modified TEXT
+);
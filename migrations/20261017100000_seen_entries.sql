@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS seen_entries (
+    feed_url TEXT NOT NULL,
+    entry_id TEXT NOT NULL,
+    first_seen_at INTEGER NOT NULL,
+    PRIMARY KEY (feed_url, entry_id)
+);
@@ -0,0 +1,13 @@
+ALTER TABLE posted_urls ADD COLUMN feed_url TEXT;
+ALTER TABLE posted_urls ADD COLUMN entry_id TEXT;
+ALTER TABLE posted_urls ADD COLUMN title TEXT;
+ALTER TABLE posted_urls ADD COLUMN posted_at INTEGER;
+
+CREATE TABLE IF NOT EXISTS post_records (
+    uri TEXT PRIMARY KEY NOT NULL,
+    cid TEXT NOT NULL,
+    url TEXT NOT NULL REFERENCES posted_urls (url) ON DELETE CASCADE,
+    account TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS post_records_url ON post_records (url);
@@ -0,0 +1,2 @@
+ALTER TABLE posted_urls ADD COLUMN content_hash TEXT;
+ALTER TABLE posted_urls ADD COLUMN entry_updated_at INTEGER;
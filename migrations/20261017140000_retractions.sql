@@ -0,0 +1,2 @@
+ALTER TABLE posted_urls ADD COLUMN missing_fetches INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE posted_urls ADD COLUMN retracted_at INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE post_records ADD COLUMN root_uri TEXT;
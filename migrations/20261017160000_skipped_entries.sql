@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS skipped_entries (
+    feed_url TEXT NOT NULL,
+    entry_id TEXT NOT NULL,
+    link TEXT NOT NULL,
+    rule TEXT NOT NULL,
+    rules_fingerprint TEXT NOT NULL,
+    skipped_at INTEGER NOT NULL,
+    PRIMARY KEY (feed_url, entry_id)
+);
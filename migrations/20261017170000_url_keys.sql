@@ -0,0 +1,4 @@
+ALTER TABLE posted_urls ADD COLUMN url_key TEXT;
+
+CREATE INDEX IF NOT EXISTS posted_urls_url_key ON posted_urls (url_key);
+CREATE INDEX IF NOT EXISTS posted_urls_entry ON posted_urls (feed_url, entry_id);
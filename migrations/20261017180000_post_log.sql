@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS post_log (
+    account TEXT NOT NULL,
+    feed_url TEXT,
+    posted_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS post_log_account ON post_log (account, posted_at);
+CREATE INDEX IF NOT EXISTS post_log_feed ON post_log (feed_url, posted_at);
+
+ALTER TABLE outbox ADD COLUMN feed_url TEXT;
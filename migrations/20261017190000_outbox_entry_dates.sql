@@ -0,0 +1 @@
+ALTER TABLE outbox ADD COLUMN entry_date INTEGER;
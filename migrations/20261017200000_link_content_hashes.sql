@@ -0,0 +1,3 @@
+-- Content hashes now include the link of the entry. Stored hashes are cleared so that they are
+-- recorded again on the next fetch, instead of every entry being edited as if it had changed.
+UPDATE posted_urls SET content_hash = NULL;
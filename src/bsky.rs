@@ -1,35 +1,111 @@
-use crate::image::resize_to_aspect_ratio;
-use anyhow::{Context, Result};
+use crate::config::ThumbnailFit;
+use crate::image::{
+    ImageOptions, PreparedImage, image_dimensions, is_static_web_image, prepare_image,
+};
+use crate::video::mp4_duration;
+use anyhow::{Context, Result, anyhow, bail};
 use bsky_sdk::{
     BskyAgent,
     agent::config::{Config, FileStore},
     api::{
         app::bsky::{
-            embed::external::{ExternalData, MainData},
+            embed::{
+                defs::{self, AspectRatioData},
+                external::{ExternalData, MainData},
+                images, video,
+            },
             feed::post::{self, RecordEmbedRefs},
+            richtext::facet::MainFeaturesItem,
         },
+        com::atproto::repo::strong_ref,
         types::{
-            Collection, TryIntoUnknown, Union,
-            string::{Datetime, Language, RecordKey},
+            Collection, TryFromUnknown, TryIntoUnknown, Union,
+            string::{Cid, Datetime, Language, RecordKey},
         },
     },
+    record::Record,
     rich_text::RichText,
 };
 use chrono::{DateTime, Utc};
-use image::{ImageFormat, imageops::FilterType};
 use log::{debug, info, warn};
 use reqwest::Url;
-use std::{path::PathBuf, str::FromStr};
+use serde::{Deserialize, Serialize};
+use std::{num::NonZeroU64, path::PathBuf, str::FromStr};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The maximum number of graphemes allowed in the text of a post.
+pub const MAX_POST_GRAPHEMES: usize = 300;
+
+/// The maximum number of tags allowed on a post record.
+pub const MAX_POST_TAGS: usize = 8;
+
+/// The maximum number of graphemes allowed in a single tag.
+pub const MAX_TAG_GRAPHEMES: usize = 64;
+
+/// The maximum number of images that can be attached to a post.
+pub const MAX_POST_IMAGES: usize = 4;
+
+/// The maximum number of graphemes allowed in the alt text of an image.
+pub const MAX_ALT_TEXT_GRAPHEMES: usize = 2000;
+
+/// The maximum number of graphemes allowed in the alt text of a video.
+pub const MAX_VIDEO_ALT_TEXT_GRAPHEMES: usize = 1000;
+
+/// The maximum size of a video that can be attached to a post.
+pub const MAX_VIDEO_BYTES: u64 = 100_000_000;
+
+/// The maximum length of a video that can be attached to a post.
+pub const MAX_VIDEO_DURATION: std::time::Duration = std::time::Duration::from_secs(180);
+
+/// A reference to a post record created on Bluesky.
+#[derive(Debug, Clone)]
+pub struct PostRef {
+    pub uri: String,
+    pub cid: String,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PostData {
     pub text: String,
     pub languages: Vec<String>,
+    pub disable_comments: bool,
     pub created_at: DateTime<Utc>,
     pub embed: Option<PostEmbed>,
+    /// The text of posts to create as a chain of replies below the post.
+    #[serde(default)]
+    pub replies: Vec<String>,
+    /// Tags added to the post record, which are not shown in the post text.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Images attached to the post in place of the link card. The link card is used instead
+    /// if none of them can be uploaded.
+    #[serde(default)]
+    pub images: Vec<PostImage>,
+    /// A video attached to the post in place of the images and link card. The link card is used
+    /// instead if the video cannot be uploaded.
+    #[serde(default)]
+    pub video: Option<PostVideo>,
+    /// The limits that uploaded images are converted to fit within.
+    #[serde(default)]
+    pub image_options: ImageOptions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostImage {
+    pub url: Url,
+    pub alt: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostVideo {
+    pub url: Url,
+    pub alt: String,
+    /// The dimensions of the video given by the feed, if any.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PostEmbed {
     pub title: String,
     pub description: String,
@@ -40,7 +116,6 @@ pub struct PostEmbed {
 pub struct BlueskyClient {
     pub agent: BskyAgent,
     pub data_path: PathBuf,
-    pub disable_comments: bool,
 }
 
 impl BlueskyClient {
@@ -54,24 +129,14 @@ impl BlueskyClient {
         }
     }
 
-    pub async fn new(
-        service: Url,
-        data_path_base: PathBuf,
-        disable_comments: bool,
-    ) -> Result<Self> {
-        let data_path = data_path_base.join("agentconfig.json");
-
+    pub async fn new(service: Url, data_path: PathBuf) -> Result<Self> {
         // Try login with cached token.
         match Config::load(&FileStore::new(&data_path)).await {
             Ok(config) => {
                 // We have a cached token, attempt to use it.
                 match BskyAgent::builder().config(config).build().await {
                     Ok(agent) => {
-                        let handler = Self {
-                            agent,
-                            data_path,
-                            disable_comments,
-                        };
+                        let handler = Self { agent, data_path };
                         handler.sync_session().await?;
                         Ok(handler)
                     }
@@ -82,7 +147,6 @@ impl BlueskyClient {
                             .build()
                             .await?,
                         data_path,
-                        disable_comments,
                     }),
                 }
             }
@@ -93,7 +157,6 @@ impl BlueskyClient {
                     .build()
                     .await?,
                 data_path,
-                disable_comments,
             }),
         }
     }
@@ -115,86 +178,392 @@ impl BlueskyClient {
         Ok(())
     }
 
-    pub async fn post(&self, post: PostData) -> Result<()> {
+    /// Build the record for a post, uploading any thumbnail it embeds.
+    async fn build_record(&self, post: &PostData) -> Result<post::RecordData> {
         info!("Constructing post data for: '{}'", &post.text);
         let rt = RichText::new_with_detect_facets(&post.text).await?;
-        let embed = match post.embed {
-            Some(data) => Some(
+        if rt.grapheme_len() > MAX_POST_GRAPHEMES {
+            bail!(
+                "post text is {} graphemes long, which is over the limit of {MAX_POST_GRAPHEMES}",
+                rt.grapheme_len()
+            );
+        }
+        let media = match &post.video {
+            Some(post_video) => self.embed_video(post_video).await,
+            None => self.embed_images(&post.images, &post.image_options).await,
+        };
+        let embed = match (media, &post.embed) {
+            (Some(media), _) => Some(media),
+            (None, Some(data)) => Some(
                 self.embed_external(
                     &data.title,
                     &data.description,
                     data.uri.as_ref(),
-                    data.thumbnail_url,
+                    data.thumbnail_url.clone(),
+                    &post.image_options,
                 )
                 .await?,
             ),
-            None => None,
+            (None, None) => None,
         };
 
+        Ok(post::RecordData {
+            created_at: Datetime::from_str(&post.created_at.fixed_offset().to_rfc3339())?,
+            embed,
+            entities: None,
+            facets: rt.facets,
+            labels: None,
+            langs: post_languages(post)?,
+            reply: None,
+            tags: (!post.tags.is_empty()).then(|| post.tags.clone()),
+            text: post.text.clone(),
+        })
+    }
+
+    /// Create a post along with any replies it has.
+    ///
+    /// Returns the created post followed by its replies in order. Failing to disable comments or to
+    /// create a reply is logged rather than returned, as the post itself has already been created, and
+    /// a failed reply cuts the thread short.
+    pub async fn post(&self, post: &PostData) -> Result<Vec<PostRef>> {
+        let record = self.build_record(post).await?;
         info!("Creating post record for: '{}'", &post.text);
-        let record = self
-            .agent
-            .create_record(post::RecordData {
-                created_at: Datetime::from_str(&post.created_at.fixed_offset().to_rfc3339())?,
-                embed,
-                entities: None,
-                facets: rt.facets,
-                labels: None,
-                langs: Some(
-                    post.languages
-                        .iter()
-                        .map(|f| Language::from_str(f).unwrap())
-                        .collect(),
-                ),
-                reply: None,
-                tags: None,
-                text: post.text,
-            })
-            .await?;
+        let record = self.agent.create_record(record).await?;
 
-        if self.disable_comments {
-            info!(
-                "Disabling post comments via threadgate for '{}'",
+        if post.disable_comments
+            && let Err(err) = self.disable_comments(&record.uri).await
+        {
+            warn!(
+                "Failed to disable comments on post '{}', it will be left open: {err:?}",
                 record.uri
             );
+        }
 
-            let rkey = record.uri.rsplit_once('/').map(|(_, rkey)| {
-                RecordKey::from_str(rkey).expect("record key from post should always be vlaid")
-            });
+        let root = PostRef {
+            uri: record.uri.clone(),
+            cid: record.cid.as_ref().to_string(),
+        };
+        let replies = self.post_replies(&root, post).await;
+        Ok([root].into_iter().chain(replies).collect())
+    }
 
-            self.agent
+    /// Disable comments on a post by creating a threadgate that allows nobody to reply.
+    async fn disable_comments(&self, uri: &str) -> Result<()> {
+        info!("Disabling post comments via threadgate for '{uri}'");
+
+        let rkey = uri.rsplit_once('/').map(|(_, rkey)| {
+            RecordKey::from_str(rkey).expect("record key from post should always be vlaid")
+        });
+
+        self.agent
+            .api
+            .com
+            .atproto
+            .repo
+            .create_record(
+                bsky_sdk::api::com::atproto::repo::create_record::InputData {
+                    collection: bsky_sdk::api::app::bsky::feed::Threadgate::nsid(),
+                    record: bsky_sdk::api::app::bsky::feed::threadgate::RecordData {
+                        allow: Some(vec![]),
+                        created_at: Datetime::now(),
+                        hidden_replies: None,
+                        post: uri.to_string(),
+                    }
+                    .try_into_unknown()?,
+                    repo: self
+                        .agent
+                        .get_session()
+                        .await
+                        .expect("not unauthenticated")
+                        .data
+                        .did
+                        .into(),
+                    rkey,
+                    swap_commit: None,
+                    validate: None,
+                }
+                .into(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Create the replies of a post as a chain below it.
+    async fn post_replies(&self, root: &PostRef, post: &PostData) -> Vec<PostRef> {
+        let mut replies: Vec<PostRef> = Vec::with_capacity(post.replies.len());
+        for text in &post.replies {
+            let parent = replies.last().unwrap_or(root);
+            let result = async {
+                let rt = RichText::new_with_detect_facets(text).await?;
+                info!("Creating reply record for: '{text}'");
+                let record = self
+                    .agent
+                    .create_record(post::RecordData {
+                        created_at: Datetime::now(),
+                        embed: None,
+                        entities: None,
+                        facets: rt.facets,
+                        labels: None,
+                        langs: post_languages(post)?,
+                        reply: Some(
+                            post::ReplyRefData {
+                                parent: strong_ref(parent)?,
+                                root: strong_ref(root)?,
+                            }
+                            .into(),
+                        ),
+                        tags: None,
+                        text: text.clone(),
+                    })
+                    .await?;
+                anyhow::Ok(PostRef {
+                    uri: record.uri.clone(),
+                    cid: record.cid.as_ref().to_string(),
+                })
+            };
+            match result.await {
+                Ok(reply) => replies.push(reply),
+                Err(err) => {
+                    warn!(
+                        "Failed to create reply to '{}', the thread will be incomplete: {err:?}",
+                        root.uri
+                    );
+                    break;
+                }
+            }
+        }
+        replies
+    }
+
+    /// Replace the contents of an existing post record, keeping its original creation date.
+    ///
+    /// The replies of the post are deleted and created again, as their number can change. Returns the
+    /// post followed by its new replies in order.
+    pub async fn edit_post(
+        &self,
+        uri: &str,
+        reply_uris: &[String],
+        post: &PostData,
+    ) -> Result<Vec<PostRef>> {
+        let (_, rkey) = split_post_uri(uri)?;
+        let rkey = RecordKey::from_str(rkey).map_err(|err| anyhow!(err))?;
+        let existing = self.get_post(rkey.clone()).await?;
+
+        let mut record = self.build_record(post).await?;
+        record.created_at = existing.data.created_at;
+        record.reply = existing.data.reply;
+        info!("Editing post record '{uri}' to: '{}'", &post.text);
+        let output = post::Record::from(record).put(&self.agent, rkey).await?;
+        for reply_uri in reply_uris {
+            self.delete_post(reply_uri).await?;
+        }
+
+        let root = PostRef {
+            uri: output.uri.clone(),
+            cid: output.cid.as_ref().to_string(),
+        };
+        let replies = self.post_replies(&root, post).await;
+        Ok([root].into_iter().chain(replies).collect())
+    }
+
+    /// Post an existing post record again with new contents, keeping its original creation date, and
+    /// delete the old post and its replies.
+    ///
+    /// The old post is only deleted once the new one has been created, so it is never lost. Failing to
+    /// delete it is logged rather than returned, as the new post already exists.
+    pub async fn repost(
+        &self,
+        uri: &str,
+        reply_uris: &[String],
+        post: &PostData,
+    ) -> Result<Vec<PostRef>> {
+        let (_, rkey) = split_post_uri(uri)?;
+        let rkey = RecordKey::from_str(rkey).map_err(|err| anyhow!(err))?;
+        let existing = self.get_post(rkey).await?;
+        let post = PostData {
+            created_at: existing.data.created_at.as_ref().to_utc(),
+            ..post.clone()
+        };
+        let thread = self.post(&post).await?;
+        for old_uri in reply_uris.iter().map(String::as_str).chain([uri]) {
+            if let Err(err) = self.delete_post(old_uri).await {
+                warn!("Failed to delete post '{old_uri}' after reposting it: {err:?}");
+            }
+        }
+        Ok(thread)
+    }
+
+    async fn get_post(&self, rkey: RecordKey) -> Result<post::Record> {
+        let output = post::Record::get(&self.agent, rkey).await?;
+        post::Record::try_from_unknown(output.data.value.clone())
+            .context("existing record is not a valid post")
+    }
+
+    /// Delete a post record along with the threadgate that may have been created for it.
+    pub async fn delete_post(&self, uri: &str) -> Result<()> {
+        let (repo, rkey) = split_post_uri(uri)?;
+
+        // Threadgates share the record key of the post they apply to, and deleting a record
+        // that does not exist succeeds, so this is safe for posts without one.
+        info!("Deleting threadgate of post '{uri}'");
+        self.agent
+            .delete_record(format!(
+                "at://{repo}/{}/{rkey}",
+                bsky_sdk::api::app::bsky::feed::Threadgate::NSID
+            ))
+            .await?;
+
+        info!("Deleting post record '{uri}'");
+        self.agent.delete_record(uri).await?;
+        Ok(())
+    }
+
+    /// Download and upload a video and build an embed for it.
+    ///
+    /// Returns `None` if the video is over the size or duration limit, does not declare its duration
+    /// or fails to be uploaded.
+    async fn embed_video(&self, post_video: &PostVideo) -> Option<Union<RecordEmbedRefs>> {
+        let result = async {
+            debug!(
+                "Fetching and uploading video blob data for '{}'",
+                post_video.url
+            );
+            let mut response = reqwest::get(post_video.url.clone())
+                .await?
+                .error_for_status()?;
+            if let Some(length) = response.content_length()
+                && length > MAX_VIDEO_BYTES
+            {
+                bail!("video is {length} bytes, which is over the limit of {MAX_VIDEO_BYTES}");
+            }
+            // Read the video in chunks so that a response without a length is not read past the limit.
+            let mut raw_video = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                raw_video.extend_from_slice(&chunk);
+                if raw_video.len() as u64 > MAX_VIDEO_BYTES {
+                    bail!("video is over the limit of {MAX_VIDEO_BYTES} bytes");
+                }
+            }
+            match mp4_duration(&raw_video) {
+                Some(duration) if duration > MAX_VIDEO_DURATION => bail!(
+                    "video is {} seconds long, which is over the limit of {} seconds",
+                    duration.as_secs(),
+                    MAX_VIDEO_DURATION.as_secs()
+                ),
+                Some(_) => {}
+                None => bail!("video does not declare its duration"),
+            }
+            let output = self
+                .agent
                 .api
                 .com
                 .atproto
                 .repo
-                .create_record(
-                    bsky_sdk::api::com::atproto::repo::create_record::InputData {
-                        collection: bsky_sdk::api::app::bsky::feed::Threadgate::nsid(),
-                        record: bsky_sdk::api::app::bsky::feed::threadgate::RecordData {
-                            allow: Some(vec![]),
-                            created_at: Datetime::now(),
-                            hidden_replies: None,
-                            post: record.uri.clone(),
-                        }
-                        .try_into_unknown()?,
-                        repo: self
-                            .agent
-                            .get_session()
-                            .await
-                            .expect("not unauthenticated")
-                            .data
-                            .did
-                            .into(),
-                        rkey,
-                        swap_commit: None,
-                        validate: None,
-                    }
-                    .into(),
-                )
+                .upload_blob(raw_video)
                 .await?;
+            anyhow::Ok(video::MainData {
+                alt: (!post_video.alt.is_empty()).then(|| post_video.alt.clone()),
+                aspect_ratio: aspect_ratio(
+                    post_video.width.unwrap_or_default(),
+                    post_video.height.unwrap_or_default(),
+                ),
+                captions: None,
+                video: output.data.blob,
+            })
         };
+        match result.await {
+            Ok(video) => Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedVideoMain(
+                Box::new(video.into()),
+            ))),
+            Err(err) => {
+                warn!(
+                    "Failed to upload video '{}', a link card will be used instead: {err:?}",
+                    post_video.url
+                );
+                None
+            }
+        }
+    }
 
-        Ok(())
+    /// Upload images and build an embed for them, skipping any that fail to be uploaded.
+    ///
+    /// Returns `None` if there are no images or none of them could be uploaded.
+    async fn embed_images(
+        &self,
+        post_images: &[PostImage],
+        options: &ImageOptions,
+    ) -> Option<Union<RecordEmbedRefs>> {
+        let mut embed_images = Vec::with_capacity(post_images.len());
+        for post_image in post_images.iter().take(MAX_POST_IMAGES) {
+            let result = async {
+                debug!(
+                    "Fetching and uploading image blob data for '{}'",
+                    post_image.url
+                );
+                let raw_image = reqwest::get(post_image.url.clone())
+                    .await?
+                    .error_for_status()?
+                    .bytes()
+                    .await?
+                    .to_vec();
+
+                // Static images that already fit are uploaded as they are to avoid re-encoding them.
+                let dimensions = is_static_web_image(&raw_image)
+                    .then(|| image_dimensions(&raw_image))
+                    .transpose()?;
+                let image = if let Some((width, height)) = dimensions
+                    && raw_image.len() as u64 <= options.max_bytes
+                    && width <= options.max_dimension
+                    && height <= options.max_dimension
+                {
+                    PreparedImage {
+                        bytes: raw_image,
+                        width,
+                        height,
+                    }
+                } else {
+                    prepare_image(&raw_image, ThumbnailFit::Keep, 1.0, options)?.with_context(
+                        || {
+                            format!(
+                                "image could not be made smaller than {} bytes",
+                                options.max_bytes
+                            )
+                        },
+                    )?
+                };
+                let output = self
+                    .agent
+                    .api
+                    .com
+                    .atproto
+                    .repo
+                    .upload_blob(image.bytes)
+                    .await?;
+                anyhow::Ok(images::ImageData {
+                    alt: post_image.alt.clone(),
+                    aspect_ratio: aspect_ratio(image.width, image.height),
+                    image: output.data.blob,
+                })
+            };
+            match result.await {
+                Ok(image) => embed_images.push(image.into()),
+                Err(err) => warn!(
+                    "Failed to upload image '{}', it will be left out of the post: {err:?}",
+                    post_image.url
+                ),
+            }
+        }
+        if embed_images.is_empty() {
+            return None;
+        }
+        Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedImagesMain(
+            Box::new(
+                images::MainData {
+                    images: embed_images,
+                }
+                .into(),
+            ),
+        )))
     }
 
     async fn embed_external(
@@ -203,39 +572,37 @@ impl BlueskyClient {
         description: &str,
         uri: &str,
         thumbnail_url: Option<Url>,
+        options: &ImageOptions,
     ) -> Result<Union<RecordEmbedRefs>> {
         info!("Constructing external embed data for: '{uri}'");
-        let thumb = if let Some(data) = thumbnail_url {
-            debug!("Fetching and uploading image blob data for '{uri}'");
-
-            // Download image and convert to 16:9.
-            let raw_image = reqwest::get(data).await?.bytes().await?;
-            let converted_image = match resize_to_aspect_ratio(
-                &raw_image,
-                16.0 / 9.0,
-                ImageFormat::WebP,
-                FilterType::Nearest,
-            ) {
-                Ok(resized_image) => resized_image,
-                Err(err) => {
-                    warn!(
-                        "Failed to convert image to a 16:9 WEBP: {err:?}. The original image will be used instead"
-                    );
-                    raw_image.to_vec()
+        let thumb = match thumbnail_url {
+            Some(url) => {
+                debug!("Fetching and uploading image blob data for '{uri}'");
+                let result = async {
+                    let Some(image) = prepare_thumbnail(url, options).await? else {
+                        return anyhow::Ok(None);
+                    };
+                    let output = self.agent.api.com.atproto.repo.upload_blob(image).await?;
+                    Ok(Some(output.data.blob))
+                };
+                match result.await {
+                    Ok(Some(thumb)) => Some(thumb),
+                    Ok(None) => {
+                        warn!(
+                            "Thumbnail for '{uri}' could not be made smaller than {} bytes, the link card will not have one",
+                            options.max_bytes
+                        );
+                        None
+                    }
+                    Err(err) => {
+                        warn!(
+                            "Failed to upload thumbnail for '{uri}', the link card will not have one: {err:?}"
+                        );
+                        None
+                    }
                 }
-            };
-
-            let output = self
-                .agent
-                .api
-                .com
-                .atproto
-                .repo
-                .upload_blob(converted_image)
-                .await?;
-            Some(output.data.blob)
-        } else {
-            None
+            }
+            None => None,
         };
         Ok(Union::Refs(RecordEmbedRefs::AppBskyEmbedExternalMain(
             Box::new(
@@ -253,3 +620,167 @@ impl BlueskyClient {
         )))
     }
 }
+
+/// Parse a list of languages in ISO-639-1 format, failing on the first invalid language.
+pub fn parse_languages(languages: &[String]) -> Result<Vec<Language>> {
+    languages
+        .iter()
+        .map(|language| {
+            Language::from_str(language)
+                .map_err(|err| anyhow!("invalid post language '{language}': {err}"))
+        })
+        .collect()
+}
+
+fn post_languages(post: &PostData) -> Result<Option<Vec<Language>>> {
+    parse_languages(&post.languages).map(Some)
+}
+
+/// Build the aspect ratio of an image or video, if both of its dimensions are known.
+fn aspect_ratio(width: u32, height: u32) -> Option<defs::AspectRatio> {
+    NonZeroU64::new(width.into())
+        .zip(NonZeroU64::new(height.into()))
+        .map(|(width, height)| AspectRatioData { width, height }.into())
+}
+
+/// Build a strong reference to a created post, used to reply to it.
+fn strong_ref(post: &PostRef) -> Result<strong_ref::Main> {
+    Ok(strong_ref::MainData {
+        cid: Cid::from_str(&post.cid).map_err(|err| anyhow!(err))?,
+        uri: post.uri.clone(),
+    }
+    .into())
+}
+
+/// Split the URI of a post record into its repository and record key.
+fn split_post_uri(uri: &str) -> Result<(&str, &str)> {
+    uri.strip_prefix("at://")
+        .and_then(|path| {
+            path.split_once(&format!("/{}/", bsky_sdk::api::app::bsky::feed::Post::NSID))
+        })
+        .with_context(|| format!("{uri} is not the URI of a post record"))
+}
+
+/// Download a thumbnail image and fit it to the thumbnail aspect ratio so it is ready to be uploaded.
+///
+/// Returns `None` if the thumbnail cannot be made to fit within the size limit.
+pub async fn prepare_thumbnail(url: Url, options: &ImageOptions) -> Result<Option<Vec<u8>>> {
+    let raw_image = reqwest::get(url).await?.bytes().await?;
+    match prepare_image(
+        &raw_image,
+        options.thumbnail_fit,
+        options.thumbnail_ratio.value(),
+        options,
+    ) {
+        Ok(image) => Ok(image.map(|image| image.bytes)),
+        Err(err)
+            if raw_image.len() as u64 <= options.max_bytes && is_static_web_image(&raw_image) =>
+        {
+            warn!(
+                "Failed to convert thumbnail to a JPEG: {err:?}. The original image will be used instead"
+            );
+            Ok(Some(raw_image.to_vec()))
+        }
+        Err(err) => Err(err.context("failed to convert thumbnail to a JPEG")),
+    }
+}
+
+/// Describe the post that would be created from the given post data in a human-readable form.
+///
+/// This detects facets the same way as posting does, and prepares the thumbnail if `prepare_thumbnails`
+/// is set, but does not upload anything.
+pub async fn describe_post(post: &PostData, prepare_thumbnails: bool) -> Result<String> {
+    let rt = RichText::new_with_detect_facets(&post.text).await?;
+    let mut description = format!(
+        "Text: {}\nLength: {} graphemes\nLanguages: {}\nCreated at: {}\nComments disabled: {}\n",
+        post.text,
+        rt.grapheme_len(),
+        post.languages.join(", "),
+        post.created_at.to_rfc3339(),
+        post.disable_comments
+    );
+    if !post.tags.is_empty() {
+        description.push_str(&format!("Tags: {}\n", post.tags.join(", ")));
+    }
+    for facet in rt.facets.iter().flatten() {
+        for feature in &facet.features {
+            let feature = match feature {
+                Union::Refs(MainFeaturesItem::Link(link)) => format!("link to {}", link.uri),
+                Union::Refs(MainFeaturesItem::Tag(tag)) => format!("tag #{}", tag.tag),
+                Union::Refs(MainFeaturesItem::Mention(mention)) => {
+                    format!("mention of {}", mention.did.as_str())
+                }
+                Union::Unknown(_) => String::from("unknown feature"),
+            };
+            description.push_str(&format!(
+                "Facet: {feature} (bytes {}..{})\n",
+                facet.index.byte_start, facet.index.byte_end
+            ));
+        }
+    }
+    if let Some(video) = &post.video {
+        description.push_str(&format!(
+            "Video: {} (alt: {})\n",
+            video.url,
+            if video.alt.is_empty() {
+                "none"
+            } else {
+                &video.alt
+            }
+        ));
+    }
+    for (index, image) in post
+        .images
+        .iter()
+        .filter(|_| post.video.is_none())
+        .enumerate()
+    {
+        description.push_str(&format!(
+            "Image {}: {} (alt: {})\n",
+            index + 1,
+            image.url,
+            if image.alt.is_empty() {
+                "none"
+            } else {
+                &image.alt
+            }
+        ));
+    }
+    if let Some(embed) = post
+        .embed
+        .as_ref()
+        .filter(|_| post.video.is_none() && post.images.is_empty())
+    {
+        description.push_str(&format!(
+            "Embed title: {}\nEmbed description: {}\nEmbed link: {}\n",
+            embed.title, embed.description, embed.uri
+        ));
+        match &embed.thumbnail_url {
+            Some(url) if !prepare_thumbnails => {
+                description.push_str(&format!("Embed thumbnail: {url}\n"))
+            }
+            Some(url) => match prepare_thumbnail(url.clone(), &post.image_options).await {
+                Ok(Some(image)) => description.push_str(&format!(
+                    "Embed thumbnail: {url} ({} bytes after conversion)\n",
+                    image.len()
+                )),
+                Ok(None) => description.push_str(&format!(
+                    "Embed thumbnail: {url} (dropped, over the limit of {} bytes)\n",
+                    post.image_options.max_bytes
+                )),
+                Err(err) => {
+                    description.push_str(&format!("Embed thumbnail: {url} (failed: {err:#})\n"))
+                }
+            },
+            None => description.push_str("Embed thumbnail: none\n"),
+        }
+    }
+    for (index, reply) in post.replies.iter().enumerate() {
+        description.push_str(&format!(
+            "Reply {}: {reply} ({} graphemes)\n",
+            index + 1,
+            reply.graphemes(true).count()
+        ));
+    }
+    Ok(description)
+}
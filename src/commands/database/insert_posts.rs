@@ -1,6 +1,8 @@
 use crate::{
     commands::{ExecutableCommand, GlobalArguments},
-    database::Database,
+    config::DEFAULT_TRACKING_PARAMS,
+    database::{Database, PostedKeys},
+    rss::canonicalize_url,
 };
 use anyhow::Result;
 use clap::Parser;
@@ -25,9 +27,15 @@ impl ExecutableCommand for InsertPostsCommand {
 
         for post in self.posts {
             let url = post.as_str();
-            if !database.has_posted_url(url).await? {
+            let url_key = canonicalize_url(url, DEFAULT_TRACKING_PARAMS);
+            let keys = PostedKeys {
+                url,
+                url_key: &url_key,
+                entry: None,
+            };
+            if database.find_posted_url(keys).await?.is_none() {
                 info!("Marking {url} as already posted");
-                database.insert_posted_url(url).await?;
+                database.insert_posted_url(url, &url_key).await?;
             } else {
                 info!("{url} is already marked as posted");
             }
@@ -1,12 +1,14 @@
 mod export_posts;
 mod insert_posts;
 mod remove_posts;
+mod show_post;
 
 use anyhow::Result;
 use clap::Parser;
 use export_posts::ExportPostsCommand;
 use insert_posts::InsertPostsCommand;
 use remove_posts::RemovePostsCommand;
+use show_post::ShowPostCommand;
 
 use super::{ExecutableCommand, GlobalArguments};
 
@@ -22,6 +24,7 @@ enum DatabaseSubcommand {
     InsertPost(InsertPostsCommand),
     RemovePost(RemovePostsCommand),
     ExportPosts(ExportPostsCommand),
+    ShowPost(ShowPostCommand),
 }
 
 impl ExecutableCommand for DatabaseCommandBase {
@@ -30,6 +33,7 @@ impl ExecutableCommand for DatabaseCommandBase {
             DatabaseSubcommand::InsertPost(cmd) => cmd.run(global_args).await,
             DatabaseSubcommand::RemovePost(cmd) => cmd.run(global_args).await,
             DatabaseSubcommand::ExportPosts(cmd) => cmd.run(global_args).await,
+            DatabaseSubcommand::ShowPost(cmd) => cmd.run(global_args).await,
         }
     }
 }
@@ -1,5 +1,8 @@
 use crate::{
-    commands::{ExecutableCommand, GlobalArguments},
+    commands::{
+        AccountArguments, ExecutableCommand, GlobalArguments,
+        delete::{delete_posts, find_posted_url},
+    },
     database::Database,
 };
 use anyhow::Result;
@@ -11,9 +14,16 @@ use reqwest::Url;
 ///
 /// Useful for making the bot repost URLs that may not have been properly posted.
 ///
-/// Please note that this does not delete the post from Bluesky itself.
+/// Please note that this does not delete the post from Bluesky itself unless `--delete-remote` is passed.
 #[derive(Debug, Parser)]
 pub struct RemovePostsCommand {
+    #[clap(flatten)]
+    account: AccountArguments,
+
+    /// Also delete the posts created for the URLs from Bluesky, along with their threadgates.
+    #[clap(long = "delete-remote")]
+    delete_remote: bool,
+
     /// A comma-seperated list of URLs to posts.
     #[clap(value_delimiter = ',', required = true)]
     posts: Vec<Url>,
@@ -23,11 +33,21 @@ impl ExecutableCommand for RemovePostsCommand {
     async fn run(self, global_args: GlobalArguments) -> Result<()> {
         let database = Database::new(&global_args.database_url).await?;
 
+        if self.delete_remote {
+            let urls = self
+                .posts
+                .iter()
+                .map(|post| post.to_string())
+                .collect::<Vec<_>>();
+            return delete_posts(&database, &urls, &self.account, &global_args).await;
+        }
+
+        let config = global_args.load_config()?;
         for post in self.posts {
             let url = post.as_str();
-            if database.has_posted_url(url).await? {
-                info!("Removing {url} from already posted list");
-                database.delete_posted_url(url).await?;
+            if let Some(posted_url) = find_posted_url(&database, &config, url).await? {
+                info!("Removing {posted_url} from already posted list");
+                database.delete_posted_url(&posted_url).await?;
             } else {
                 info!("{url} is not marked as posted");
             }
@@ -0,0 +1,66 @@
+use crate::{
+    commands::{ExecutableCommand, GlobalArguments},
+    database::Database,
+};
+use anyhow::{Result, bail};
+use clap::Parser;
+
+/// Show what is stored about a posted URL and the Bluesky posts created for it.
+///
+/// Accepts either the URL of a feed entry or the `at://` URI of a post record.
+#[derive(Debug, Parser)]
+pub struct ShowPostCommand {
+    /// The URL of a posted feed entry, or the URI of a post record.
+    post: String,
+}
+
+impl ExecutableCommand for ShowPostCommand {
+    async fn run(self, global_args: GlobalArguments) -> Result<()> {
+        let database = Database::new(&global_args.database_url).await?;
+
+        let url = if self.post.starts_with("at://") {
+            match database.get_post_record(&self.post).await? {
+                Some(record) => record.url,
+                None => bail!("no post record with the URI {} is stored", self.post),
+            }
+        } else {
+            self.post
+        };
+        let Some(posted) = database.get_posted_url(&url).await? else {
+            bail!("{url} is not marked as posted");
+        };
+
+        println!("URL: {}", posted.url);
+        println!("Feed: {}", posted.feed_url.as_deref().unwrap_or("unknown"));
+        println!(
+            "Entry ID: {}",
+            posted.entry_id.as_deref().unwrap_or("unknown")
+        );
+        println!("Title: {}", posted.title.as_deref().unwrap_or("unknown"));
+        println!(
+            "Posted at: {}",
+            posted
+                .posted_at
+                .map_or_else(|| String::from("unknown"), |date| date.to_rfc3339())
+        );
+        if let Some(retracted_at) = posted.retracted_at {
+            println!(
+                "Retracted at: {} (the entry was removed from its feed)",
+                retracted_at.to_rfc3339()
+            );
+        }
+        for record in database.get_post_records_for_url(&url).await? {
+            let kind = if record.root_uri.is_some() {
+                "Reply record"
+            } else {
+                "Record"
+            };
+            println!(
+                "{kind} for account '{}': {} (CID {})",
+                record.account, record.uri, record.cid
+            );
+        }
+
+        Ok(())
+    }
+}
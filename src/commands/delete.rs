@@ -0,0 +1,131 @@
+use super::{AccountArguments, ExecutableCommand, GlobalArguments, login_accounts};
+use crate::config::{ConfigFile, DEFAULT_TRACKING_PARAMS};
+use crate::database::{Database, PostedKeys};
+use crate::rss::canonicalize_url;
+use anyhow::{Result, bail};
+use clap::Parser;
+use log::info;
+
+/// Delete posts from Bluesky and remove their URLs from the posted_urls table.
+///
+/// Every post created for a URL is deleted along with its threadgate, using the account that created it.
+/// Any pending retries of the URL in the outbox are discarded.
+#[derive(Debug, Parser)]
+pub struct DeleteCommand {
+    #[clap(flatten)]
+    account: AccountArguments,
+
+    /// A comma-seperated list of URLs of posted feed entries, or URIs of post records.
+    #[clap(value_delimiter = ',', required = true)]
+    posts: Vec<String>,
+}
+
+impl ExecutableCommand for DeleteCommand {
+    async fn run(self, global_args: GlobalArguments) -> Result<()> {
+        let database = Database::new(&global_args.database_url).await?;
+        let mut urls = Vec::new();
+        for post in self.posts {
+            if post.starts_with("at://") {
+                match database.get_post_record(&post).await? {
+                    Some(record) => urls.push(record.url),
+                    None => bail!("no post record with the URI {post} is stored"),
+                }
+            } else {
+                urls.push(post);
+            }
+        }
+        delete_posts(&database, &urls, &self.account, &global_args).await
+    }
+}
+
+/// Delete every post created for the given URLs from Bluesky, then remove the URLs from the database.
+///
+/// A URL is only removed once all of its posts have been deleted, so a failed deletion can be retried.
+pub async fn delete_posts(
+    database: &Database,
+    urls: &[String],
+    account: &AccountArguments,
+    global_args: &GlobalArguments,
+) -> Result<()> {
+    let config = global_args.load_config()?;
+    let mut records = Vec::new();
+    for url in urls {
+        let Some(url) = find_posted_url(database, &config, url).await? else {
+            info!("{url} is not marked as posted");
+            continue;
+        };
+        let post_records = database.get_post_records_for_url(&url).await?;
+        records.push((url, post_records));
+    }
+
+    // Only login to the accounts that actually have posts to delete.
+    let accounts = account.resolve_accounts(
+        records
+            .iter()
+            .flat_map(|(_, records)| records.iter().map(|record| record.account.as_str())),
+        config.accounts,
+        &global_args.data_path,
+    )?;
+    let bsky_clients = login_accounts(accounts).await?;
+
+    for (url, records) in records {
+        if records.is_empty() {
+            info!("No post records are stored for {url}, only removing it from the database");
+        }
+        for record in records {
+            bsky_clients[&record.account]
+                .delete_post(&record.uri)
+                .await?;
+        }
+        info!("Removing {url} from already posted list");
+        database.delete_outbox_entries_for_url(&url).await?;
+        database.delete_posted_url(&url).await?;
+    }
+    for bsky_client in bsky_clients.values() {
+        bsky_client.sync_session().await?;
+    }
+
+    Ok(())
+}
+
+/// Find the URL a link was stored under, preferring an exact match over a canonicalized one.
+///
+/// As the feed a link was posted from is not known, it is canonicalized with the settings of every feed
+/// in the configuration file in turn.
+pub async fn find_posted_url(
+    database: &Database,
+    config: &ConfigFile,
+    url: &str,
+) -> Result<Option<String>> {
+    if database.get_posted_url(url).await?.is_some() {
+        return Ok(Some(url.to_string()));
+    }
+    let mut url_keys = Vec::new();
+    let settings = config
+        .feeds
+        .iter()
+        .map(|feed| feed.settings.clone().or(&config.defaults))
+        .chain([config.defaults.clone()]);
+    for settings in settings {
+        if settings.canonicalize_urls == Some(false) {
+            continue;
+        }
+        let url_key = match &settings.tracking_params {
+            Some(tracking_params) => canonicalize_url(url, tracking_params),
+            None => canonicalize_url(url, DEFAULT_TRACKING_PARAMS),
+        };
+        if url_keys.contains(&url_key) {
+            continue;
+        }
+        let keys = PostedKeys {
+            url,
+            url_key: &url_key,
+            entry: None,
+        };
+        if let Some(posted_url) = database.find_posted_url(keys).await? {
+            return Ok(Some(posted_url));
+        }
+        url_keys.push(url_key);
+    }
+    Ok(None)
+}
@@ -1,20 +1,114 @@
 mod database;
+mod delete;
+mod preview;
 mod start;
 
 use std::{
+    collections::BTreeMap,
     fs::{create_dir_all, exists},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use anyhow::{Context, Result};
-use clap::Parser;
+use crate::bsky::BlueskyClient;
+use crate::config::{AccountConfig, AccountOptions, ConfigFile, DEFAULT_ACCOUNT};
+use anyhow::{Context, Result, bail};
+use clap::{Args, Parser};
 use database::DatabaseCommandBase;
+use delete::DeleteCommand;
+use log::info;
+use preview::PreviewCommand;
+use reqwest::Url;
 use start::StartCommand;
 
 #[derive(Debug)]
 pub struct GlobalArguments {
     data_path: PathBuf,
     database_url: String,
+    config_path: Option<PathBuf>,
+}
+
+impl GlobalArguments {
+    /// Load the configuration file from `--config-path`, or from `{data-path}/config.toml` if it exists.
+    pub fn load_config(&self) -> Result<ConfigFile> {
+        match &self.config_path {
+            Some(path) => ConfigFile::load(path, true),
+            None => ConfigFile::load(&self.data_path.join("config.toml"), false),
+        }
+    }
+}
+
+/// Arguments used to configure the default account.
+#[derive(Debug, Args)]
+pub struct AccountArguments {
+    /// The base URL of the service to communicate with.
+    ///
+    /// Note that that you must delete the file at `{data-path}/agentconfig.json` to change this after it has been initially set.
+    #[clap(long = "app-service", env = "SKYWRITE_APP_SERVICE")]
+    service: Option<Url>,
+
+    /// The username or email of the application's account.
+    #[clap(long = "app-identifier", env = "SKYWRITE_APP_IDENTIFIER")]
+    identifier: Option<String>,
+
+    /// The app password to use for authentication.
+    #[clap(long = "app-password", env = "SKYWRITE_APP_PASSWORD")]
+    password: Option<String>,
+}
+
+impl AccountArguments {
+    /// Resolve the accounts with the given names from the configuration file and command-line arguments.
+    ///
+    /// The command-line arguments configure the default account and take precedence over
+    /// any default account in the configuration file.
+    pub fn resolve_accounts<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+        mut config_accounts: BTreeMap<String, AccountConfig>,
+        data_path: &Path,
+    ) -> Result<BTreeMap<String, AccountOptions>> {
+        let mut accounts = BTreeMap::new();
+        for name in names {
+            if accounts.contains_key(name) {
+                continue;
+            }
+            let account = match (name, config_accounts.remove(name)) {
+                (DEFAULT_ACCOUNT, account) => AccountConfig {
+                    service: self.service.clone(),
+                    identifier: self.identifier.clone(),
+                    password: self.password.clone(),
+                    session_file: None,
+                    max_posts_per_hour: None,
+                    max_posts_per_day: None,
+                }
+                .or(account.unwrap_or_default()),
+                (_, Some(account)) => account,
+                (_, None) => bail!("account '{name}' has not been configured"),
+            };
+            accounts.insert(
+                name.to_string(),
+                AccountOptions::resolve(name, account, data_path)?,
+            );
+        }
+        Ok(accounts)
+    }
+}
+
+/// Log in to every account, returning a client for each of them by name.
+pub async fn login_accounts(
+    accounts: BTreeMap<String, AccountOptions>,
+) -> Result<BTreeMap<String, Arc<BlueskyClient>>> {
+    let mut bsky_clients = BTreeMap::new();
+    for (name, account) in accounts {
+        info!("Logging in to account '{name}'");
+        let bsky_client = BlueskyClient::new(account.service, account.session_path).await?;
+        bsky_client
+            .login(&account.identifier, &account.password)
+            .await
+            .with_context(|| format!("failed to login to account '{name}'"))?;
+        bsky_clients.insert(name, Arc::new(bsky_client));
+    }
+    Ok(bsky_clients)
 }
 
 pub trait ExecutableCommand {
@@ -46,12 +140,19 @@ pub struct CommandRoot {
         global = true
     )]
     database_url: String,
+
+    /// The path to the configuration file used to configure feeds individually.
+    /// Defaults to `{data-path}/config.toml` if that file exists.
+    #[arg(long = "config-path", env = "SKYWRITE_CONFIG_PATH", global = true)]
+    config_path: Option<PathBuf>,
 }
 
 #[derive(Debug, Parser)]
 enum Commands {
     Start(Box<StartCommand>),
     Database(DatabaseCommandBase),
+    Preview(PreviewCommand),
+    Delete(DeleteCommand),
 }
 
 impl CommandRoot {
@@ -63,10 +164,13 @@ impl CommandRoot {
         let global_args = GlobalArguments {
             data_path: self.data_path,
             database_url: self.database_url,
+            config_path: self.config_path,
         };
         match self.command {
             Commands::Start(cmd) => cmd.run(global_args).await,
             Commands::Database(cmd) => cmd.run(global_args).await,
+            Commands::Preview(cmd) => cmd.run(global_args).await,
+            Commands::Delete(cmd) => cmd.run(global_args).await,
         }
     }
 }
@@ -0,0 +1,139 @@
+use super::{ExecutableCommand, GlobalArguments};
+use crate::bsky::describe_post;
+use crate::compose::{PostComposer, fetch_page};
+use crate::config::{FeedOptions, FeedSettings};
+use crate::filter::EntryFilter;
+use crate::rss::{UnpostedEntry, resolve_date, select_link};
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use clap::Parser;
+use log::warn;
+use reqwest::Url;
+use std::{collections::HashMap, fs, path::PathBuf};
+
+/// Print the posts that would be created for the entries of a feed without posting anything.
+///
+/// Every entry in the feed is previewed, regardless of its date or whether it has already been posted.
+#[derive(Debug, Parser)]
+pub struct PreviewCommand {
+    /// A URL pointing directly to an RSS feed, or the path to a saved feed file.
+    source: String,
+
+    /// A saved HTML page to use for an entry instead of fetching it, in the format `{entry-url}={path}`.
+    /// Can be passed multiple times.
+    #[clap(long = "page", value_parser = parse_page)]
+    pages: Vec<(String, PathBuf)>,
+
+    /// Never fetch anything from the network other than the feed itself.
+    /// Entries without a saved page are previewed as if their page was empty.
+    #[clap(long = "offline")]
+    offline: bool,
+
+    /// The URL of the feed in the configuration file to take settings from.
+    /// Defaults to the source when it is a URL.
+    #[clap(long = "feed-url")]
+    feed_url: Option<Url>,
+
+    /// The template used to generate the text of posts, overriding the feed's settings.
+    #[clap(long = "post-template")]
+    post_template: Option<String>,
+
+    /// The maximum number of entries to preview.
+    #[clap(long = "limit")]
+    limit: Option<usize>,
+}
+
+fn parse_page(value: &str) -> Result<(String, PathBuf)> {
+    let Some((url, path)) = value.rsplit_once('=') else {
+        bail!("expected a value in the format {{entry-url}}={{path}}");
+    };
+    Ok((url.to_string(), PathBuf::from(path)))
+}
+
+impl ExecutableCommand for PreviewCommand {
+    async fn run(self, global_args: GlobalArguments) -> Result<()> {
+        let source_url = Url::parse(&self.source)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"));
+        let content = match &source_url {
+            Some(url) => reqwest::get(url.as_str())
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?
+                .to_vec(),
+            None => fs::read(&self.source)
+                .with_context(|| format!("failed to read feed file at {}", self.source))?,
+        };
+        let mut feed = feed_rs::parser::parse(&content[..]).context("failed to parse feed")?;
+
+        // Use the settings of the matching feed in the configuration file, if there is one.
+        let config = global_args.load_config()?;
+        let feed_url = self.feed_url.clone().or(source_url);
+        let settings = config
+            .feeds
+            .into_iter()
+            .find(|feed| Some(&feed.url) == feed_url.as_ref())
+            .map(|feed| feed.settings)
+            .unwrap_or_default()
+            .or(&config.defaults);
+        let options = FeedOptions::resolve(
+            match &feed_url {
+                Some(url) => url.clone(),
+                None => Url::from_file_path(fs::canonicalize(&self.source)?)
+                    .expect("canonicalized path should be absolute"),
+            },
+            FeedSettings {
+                post_template: self.post_template.clone(),
+                ..Default::default()
+            }
+            .or(&settings),
+        )?;
+        let composer = PostComposer::new(&options.post_template)?;
+        let filter = EntryFilter::new(&config.blocklist, &options.include, &options.exclude)?;
+        let pages = self.pages.into_iter().collect::<HashMap<_, _>>();
+
+        let entries = std::mem::take(&mut feed.entries);
+        let limit = self.limit.unwrap_or(entries.len());
+        for mut entry in entries.into_iter().take(limit) {
+            let Some(link) = select_link(&mut entry, feed_url.as_ref()) else {
+                println!("Entry '{}' has no links and would be skipped\n", entry.id);
+                continue;
+            };
+            let Some(date) = resolve_date(&options.date_sources, &entry, Some(Utc::now())) else {
+                println!("Entry '{link}' has no date and would be skipped\n");
+                continue;
+            };
+            if let Some(rule) = filter.check(&entry, &link) {
+                println!("Entry '{link}' matched {rule} and would be skipped\n");
+                continue;
+            }
+
+            let page = match pages.get(&link) {
+                Some(path) => fs::read_to_string(path)
+                    .with_context(|| format!("failed to read saved page at {path:?}"))?,
+                None if self.offline => String::new(),
+                None => fetch_page(&link).await.unwrap_or_else(|err| {
+                    warn!("Failed to get page data for {link}: {err}");
+                    String::new()
+                }),
+            };
+
+            let unposted = UnpostedEntry {
+                entry,
+                url_key: link.clone(),
+                link,
+                posted_url: None,
+                date,
+            };
+            let post_data = composer.compose(&feed, &unposted, &page, &options)?;
+            println!(
+                "Entry '{}':\n{}",
+                unposted.link,
+                describe_post(&post_data, !self.offline).await?
+            );
+        }
+
+        Ok(())
+    }
+}
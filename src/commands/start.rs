@@ -1,65 +1,63 @@
-use super::{ExecutableCommand, GlobalArguments};
-use crate::bsky::{BlueskyClient, PostData, PostEmbed};
-use crate::database::Database;
-use crate::rss::RssHandler;
-use anyhow::{Context, Result};
-use chrono::{DateTime, Duration};
+use super::{AccountArguments, ExecutableCommand, GlobalArguments, login_accounts};
+use crate::bsky::{BlueskyClient, PostData, describe_post};
+use crate::compose::{PostComposer, fetch_page};
+use crate::config::{
+    AspectRatio, CategoryTags, ConfigFile, DEFAULT_TRACKING_PARAMS, DateSource, EmbedMode,
+    FeedOptions, FeedSettings, FilterRuleConfig, ThumbnailFit, UpdateMode,
+};
+use crate::database::{Database, PostRecord, PostedUrl};
+use crate::filter::EntryFilter;
+use crate::outbox::Outbox;
+use crate::ratelimit::{PostLimits, RateLimiter, Slot};
+use crate::rss::{RssHandler, UnpostedEntry, canonicalize_url, content_hash, is_link_gone};
+use crate::shutdown::{self, Shutdown};
+use anyhow::{Context, Result, bail};
+use chrono::{Duration, Utc};
 use clap::Parser;
+use feed_rs::model::Feed;
 use futures::future;
 use log::{debug, error, info, warn};
 use reqwest::Url;
-use scraper::{Html, Selector};
+use std::collections::HashSet;
+use std::num::NonZeroU32;
 use std::primitive;
 use std::sync::Arc;
 use tokio::time::sleep;
 
+/// How often the outbox is checked for posts that are due to be retried.
+const OUTBOX_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
 /// Start the bot and begin checking for new RSS posts on an interval.
 #[derive(Debug, Parser)]
 pub struct StartCommand {
-    /// The base URL of the service to communicate with.
-    ///
-    /// Note that that you must delete the file at `{data-path}/agentconfig.json` to change this after it has been initially set.
-    #[clap(
-        default_value = "https://bsky.social",
-        long = "app-service",
-        env = "SKYWRITE_APP_SERVICE"
-    )]
-    service: Url,
-
-    /// The username or email of the application's account.
-    #[clap(
-        required = true,
-        long = "app-identifier",
-        env = "SKYWRITE_APP_IDENTIFIER"
-    )]
-    identifier: String,
-
-    /// The app password to use for authentication.
-    #[clap(required = true, long = "app-password", env = "SKYWRITE_APP_PASSWORD")]
-    password: String,
+    #[clap(flatten)]
+    account: AccountArguments,
 
     /// The interval of time in seconds between checking for new posts.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to 300.
     #[clap(
-        default_value_t = 300,
         long = "rerun-interval-seconds",
         env = "SKYWRITE_RERUN_INTERVAL_SECONDS"
     )]
-    run_interval_seconds: u64,
+    run_interval_seconds: Option<u64>,
 
     /// The number of hours in the past the bot should check for posts that haven't been posted at startup.
     /// Useful for backdating an account or when an outage occurs.
     ///
     /// It is recommended to set this to a minimum of "1" as otherwise posts may get missed.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to 3.
     #[clap(
-        default_value_t = 3,
         long = "rss-feed-backdate-hours",
         env = "SKYWRITE_RSS_FEED_BACKDATE_HOURS"
     )]
-    rss_feed_backdate_hours: u16,
+    rss_feed_backdate_hours: Option<u16>,
 
     /// A comma-seperated list of URLs pointing directly to RSS feeds.
+    ///
+    /// These feeds are checked in addition to any feeds in the configuration file and use the default settings.
     #[clap(
-        required = true,
         long = "rss-feed-urls",
         env = "SKYWRITE_RSS_FEED_URLS",
         value_delimiter = ','
@@ -67,175 +65,1046 @@ pub struct StartCommand {
     rss_feed_urls: Vec<Url>,
 
     /// Whether Bluesky posts should have comments disabled.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to true.
     #[clap(
-        default_value_t = true,
         long = "disable-post-comments",
-        env = "SKYWRITE_DISABLE_POST_COMMENTS"
+        env = "SKYWRITE_DISABLE_POST_COMMENTS",
+        num_args = 0..=1,
+        default_missing_value = "true"
     )]
-    disable_post_comments: primitive::bool,
+    disable_post_comments: Option<primitive::bool>,
 
     /// A comma-seperated list of languages in ISO-639-1 format to classify posts under.
     /// This should corrolate to the language of the posts the feed is linking to.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to "en".
     #[clap(
-        required = true,
-        default_value = "en",
         long = "post-languages",
         env = "SKYWRITE_POST_LANGUAGES",
         value_delimiter = ','
     )]
-    post_languages: Vec<String>,
+    post_languages: Option<Vec<String>>,
+
+    /// The template used to generate the text of posts.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to `{{ title or "New post" }} - {{ link }}`.
+    #[clap(long = "post-template", env = "SKYWRITE_POST_TEMPLATE")]
+    post_template: Option<String>,
+
+    /// A comma-seperated list of sources to take the date of feed entries from, in order of preference.
+    /// Entries without a date from any of these sources are skipped.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to "published,updated,first-seen".
+    #[clap(
+        long = "date-sources",
+        env = "SKYWRITE_DATE_SOURCES",
+        value_delimiter = ','
+    )]
+    date_sources: Option<Vec<DateSource>>,
+
+    /// How changes to entries that have already been posted are handled.
+    /// Changes are detected from the entry's updated date and a hash of its content.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to "off".
+    #[clap(long = "update-mode", env = "SKYWRITE_UPDATE_MODE")]
+    update_mode: Option<UpdateMode>,
+
+    /// Whether posts should be deleted from Bluesky when their entry is removed from the feed.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to false.
+    #[clap(
+        long = "retract-missing-entries",
+        env = "SKYWRITE_RETRACT_MISSING_ENTRIES",
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    retract_missing_entries: Option<primitive::bool>,
+
+    /// The number of consecutive fetches an entry must be missing from before its posts are deleted.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to 3.
+    #[clap(
+        long = "retract-after-missing-fetches",
+        env = "SKYWRITE_RETRACT_AFTER_MISSING_FETCHES",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    retract_after_missing_fetches: Option<u32>,
+
+    /// The number of hours after posting that an entry can still be retracted.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to 24.
+    #[clap(long = "retract-window-hours", env = "SKYWRITE_RETRACT_WINDOW_HOURS")]
+    retract_window_hours: Option<u16>,
+
+    /// Whether the content of entries should be posted as a thread of replies below the post.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to false.
+    #[clap(
+        long = "thread-entries",
+        env = "SKYWRITE_THREAD_ENTRIES",
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    thread_entries: Option<primitive::bool>,
+
+    /// Where the categories of entries are added to posts, as record tags, visible hashtags or both.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to "off".
+    #[clap(long = "category-tags", env = "SKYWRITE_CATEGORY_TAGS")]
+    category_tags: Option<CategoryTags>,
+
+    /// A comma-seperated list of the only categories that are added to posts.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to allowing every category.
+    #[clap(
+        long = "category-allow",
+        env = "SKYWRITE_CATEGORY_ALLOW",
+        value_delimiter = ','
+    )]
+    category_allow: Option<Vec<String>>,
+
+    /// A comma-seperated list of categories that are never added to posts.
+    ///
+    /// Overrides the value set in the configuration file defaults.
+    #[clap(
+        long = "category-deny",
+        env = "SKYWRITE_CATEGORY_DENY",
+        value_delimiter = ','
+    )]
+    category_deny: Option<Vec<String>>,
+
+    /// Whether links are canonicalized before checking if they have already been posted, so that links
+    /// that only differ by tracking parameters, scheme, `www.`, trailing slashes or fragments are only posted once.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to true.
+    #[clap(
+        long = "canonicalize-urls",
+        env = "SKYWRITE_CANONICALIZE_URLS",
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    canonicalize_urls: Option<primitive::bool>,
+
+    /// A comma-seperated list of query parameters removed from links when canonicalizing them.
+    /// A trailing `*` matches any parameter starting with the text before it.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to common tracking parameters such as "utm_*".
+    #[clap(
+        long = "tracking-params",
+        env = "SKYWRITE_TRACKING_PARAMS",
+        value_delimiter = ','
+    )]
+    tracking_params: Option<Vec<String>>,
+
+    /// Whether entries with the same ID (such as an RSS `<guid>` or Atom `<id>`) as an entry that has
+    /// already been posted from the same feed are skipped.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to true.
+    #[clap(
+        long = "dedup-entry-ids",
+        env = "SKYWRITE_DEDUP_ENTRY_IDS",
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    dedup_entry_ids: Option<primitive::bool>,
+
+    /// What is embedded in posts alongside their text, either a link card or the images of the entry.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to "link".
+    #[clap(long = "embed-mode", env = "SKYWRITE_EMBED_MODE")]
+    embed_mode: Option<EmbedMode>,
+
+    /// The maximum size in bytes of an uploaded image or thumbnail. Larger images are downscaled and
+    /// compressed until they fit, and thumbnails that still do not fit are left out of the link card.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to 1000000.
+    #[clap(long = "max-image-bytes", env = "SKYWRITE_MAX_IMAGE_BYTES")]
+    max_image_bytes: Option<u64>,
+
+    /// How thumbnails are fitted to the aspect ratio of link cards.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to "crop".
+    #[clap(long = "thumbnail-fit", env = "SKYWRITE_THUMBNAIL_FIT")]
+    thumbnail_fit: Option<ThumbnailFit>,
+
+    /// The aspect ratio thumbnails are fitted to, in the format `{width}:{height}`.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to "16:9".
+    #[clap(long = "thumbnail-ratio", env = "SKYWRITE_THUMBNAIL_RATIO")]
+    thumbnail_ratio: Option<AspectRatio>,
+
+    /// A comma-seperated list of keywords that skip matching entries from every feed.
+    ///
+    /// These are added to any rules in the blocklist of the configuration file.
+    #[clap(long = "blocklist", env = "SKYWRITE_BLOCKLIST", value_delimiter = ',')]
+    blocklist: Vec<String>,
+
+    /// The maximum number of entries from each feed that an account posts in an hour.
+    /// Entries over the limit are queued until they fit.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to no limit.
+    #[clap(long = "max-posts-per-hour", env = "SKYWRITE_MAX_POSTS_PER_HOUR")]
+    max_posts_per_hour: Option<NonZeroU32>,
+
+    /// The maximum number of entries from each feed that an account posts in a day.
+    /// Entries over the limit are queued until they fit.
+    ///
+    /// Overrides the value set in the configuration file defaults. Defaults to no limit.
+    #[clap(long = "max-posts-per-day", env = "SKYWRITE_MAX_POSTS_PER_DAY")]
+    max_posts_per_day: Option<NonZeroU32>,
+
+    /// The maximum number of posts an account creates in an hour across every feed,
+    /// for accounts that do not set their own limit in the configuration file.
+    #[clap(
+        long = "account-max-posts-per-hour",
+        env = "SKYWRITE_ACCOUNT_MAX_POSTS_PER_HOUR"
+    )]
+    account_max_posts_per_hour: Option<NonZeroU32>,
+
+    /// The maximum number of posts an account creates in a day across every feed,
+    /// for accounts that do not set their own limit in the configuration file.
+    #[clap(
+        long = "account-max-posts-per-day",
+        env = "SKYWRITE_ACCOUNT_MAX_POSTS_PER_DAY"
+    )]
+    account_max_posts_per_day: Option<NonZeroU32>,
+
+    /// The minimum time in seconds between two posts by the same account.
+    /// Entries found within this time of the last post are queued until it has passed.
+    #[clap(
+        default_value_t = 0,
+        long = "min-post-gap-seconds",
+        env = "SKYWRITE_MIN_POST_GAP_SECONDS"
+    )]
+    min_post_gap_seconds: u64,
+
+    /// The maximum number of times a post is attempted before it is given up on.
+    #[clap(
+        default_value_t = 8,
+        long = "outbox-max-attempts",
+        env = "SKYWRITE_OUTBOX_MAX_ATTEMPTS",
+        value_parser = clap::value_parser!(u32).range(2..)
+    )]
+    outbox_max_attempts: u32,
+
+    /// The time in seconds to wait before retrying a failed post.
+    /// This is doubled after every failed attempt, up to a maximum of 6 hours.
+    #[clap(
+        default_value_t = 60,
+        long = "outbox-retry-delay-seconds",
+        env = "SKYWRITE_OUTBOX_RETRY_DELAY_SECONDS"
+    )]
+    outbox_retry_delay_seconds: u64,
+
+    /// The maximum time in seconds to wait for in-flight posts to finish when shutting down.
+    #[clap(
+        default_value_t = 8,
+        long = "shutdown-timeout-seconds",
+        env = "SKYWRITE_SHUTDOWN_TIMEOUT_SECONDS"
+    )]
+    shutdown_timeout_seconds: u64,
+
+    /// Check feeds and print the posts that would be created instead of posting them.
+    ///
+    /// Nothing is posted to Bluesky and posted URLs are not stored in the database.
+    #[clap(long = "dry-run", env = "SKYWRITE_DRY_RUN")]
+    dry_run: bool,
+}
+
+impl StartCommand {
+    /// Resolve the settings of every feed from the configuration file and command-line arguments.
+    ///
+    /// Settings are resolved in order of the feed's own block, command-line arguments,
+    /// the configuration file defaults and finally the built-in defaults.
+    fn resolve_feeds(&self, config: ConfigFile) -> Result<Vec<FeedOptions>> {
+        let defaults = FeedSettings {
+            rerun_interval_seconds: self.run_interval_seconds,
+            backdate_hours: self.rss_feed_backdate_hours,
+            disable_post_comments: self.disable_post_comments,
+            post_languages: self.post_languages.clone(),
+            accounts: None,
+            post_template: self.post_template.clone(),
+            date_sources: self.date_sources.clone(),
+            update_mode: self.update_mode,
+            retract_missing_entries: self.retract_missing_entries,
+            retract_after_missing_fetches: self.retract_after_missing_fetches,
+            retract_window_hours: self.retract_window_hours,
+            thread_entries: self.thread_entries,
+            category_tags: self.category_tags,
+            category_allow: self.category_allow.clone(),
+            category_deny: self.category_deny.clone(),
+            category_rename: None,
+            include: None,
+            exclude: None,
+            canonicalize_urls: self.canonicalize_urls,
+            tracking_params: self.tracking_params.clone(),
+            dedup_entry_ids: self.dedup_entry_ids,
+            max_posts_per_hour: self.max_posts_per_hour,
+            max_posts_per_day: self.max_posts_per_day,
+            embed_mode: self.embed_mode,
+            max_image_bytes: self.max_image_bytes,
+            thumbnail_fit: self.thumbnail_fit,
+            thumbnail_ratio: self.thumbnail_ratio,
+        }
+        .or(&config.defaults);
+
+        let mut feeds: Vec<FeedOptions> = config
+            .feeds
+            .into_iter()
+            .map(|feed| FeedOptions::resolve(feed.url, feed.settings.or(&defaults)))
+            .collect::<Result<_>>()?;
+        for url in &self.rss_feed_urls {
+            if feeds.iter().any(|feed| &feed.url == url) {
+                warn!("Feed {url} is configured more than once, ignoring duplicate");
+                continue;
+            }
+            feeds.push(FeedOptions::resolve(url.clone(), defaults.clone())?);
+        }
+
+        if feeds.is_empty() {
+            bail!(
+                "no feeds have been configured, set --rss-feed-urls or add feeds to the configuration file"
+            );
+        }
+        if let Some(feed) = feeds.iter().find(|feed| feed.accounts.is_empty()) {
+            bail!("feed {} does not have any accounts to post to", feed.url);
+        }
+        Ok(feeds)
+    }
 }
 
 impl ExecutableCommand for StartCommand {
     async fn run(self, global_args: GlobalArguments) -> Result<()> {
-        let bsky_client = Arc::new(
-            BlueskyClient::new(
-                self.service,
-                global_args.data_path,
-                self.disable_post_comments,
-            )
-            .await?,
-        );
-        bsky_client.login(&self.identifier, &self.password).await?;
+        let mut config = global_args.load_config()?;
+        let config_accounts = std::mem::take(&mut config.accounts);
+        let mut blocklist = std::mem::take(&mut config.blocklist);
+        blocklist.extend(self.blocklist.iter().map(|keyword| FilterRuleConfig {
+            fields: Vec::new(),
+            keyword: Some(keyword.clone()),
+            regex: None,
+        }));
+        let feeds = self.resolve_feeds(config)?;
+        let accounts = self.account.resolve_accounts(
+            feeds
+                .iter()
+                .flat_map(|feed| feed.accounts.iter().map(String::as_str)),
+            config_accounts,
+            &global_args.data_path,
+        )?;
+
+        let account_limits = accounts
+            .iter()
+            .map(|(name, account)| {
+                let limits = PostLimits {
+                    per_hour: account
+                        .max_posts_per_hour
+                        .or(self.account_max_posts_per_hour),
+                    per_day: account.max_posts_per_day.or(self.account_max_posts_per_day),
+                };
+                (name.clone(), limits)
+            })
+            .collect();
+        let feed_limits = feeds
+            .iter()
+            .map(|feed| {
+                let limits = PostLimits {
+                    per_hour: feed.max_posts_per_hour,
+                    per_day: feed.max_posts_per_day,
+                };
+                (feed.url.to_string(), limits)
+            })
+            .collect();
+
+        // Login to every account that is used by at least one feed.
+        let bsky_clients = login_accounts(accounts).await?;
         let database = Arc::new(Database::new(&global_args.database_url).await?);
+        // Dry runs leave the database untouched, so URLs posted before keys were stored are only
+        // recognised by their exact link until a real run stores their keys.
+        if !self.dry_run {
+            backfill_url_keys(&database, &feeds).await?;
+        }
+        let rate_limiter = Arc::new(RateLimiter::new(
+            Arc::clone(&database),
+            Duration::seconds(self.min_post_gap_seconds as i64),
+            account_limits,
+            feed_limits,
+        ));
+        let outbox = Arc::new(Outbox::new(
+            Arc::clone(&database),
+            Arc::clone(&rate_limiter),
+            self.outbox_max_attempts,
+            Duration::seconds(self.outbox_retry_delay_seconds as i64),
+        ));
+        let bsky_clients = Arc::new(bsky_clients);
 
-        // Initialise html selectors.
-        let og_description_selector = Selector::parse(r#"meta[property="og:description"]"#)
-            .expect("selector expression should be parseable");
-        let og_image_selector = Selector::parse(r#"meta[property="og:image"]"#)
-            .expect("selector expression should be parseable");
+        let (shutdown_trigger, shutdown) = shutdown::channel();
 
-        let mut handles = Vec::with_capacity(self.rss_feed_urls.len());
-        for feed in self.rss_feed_urls {
-            let mut rss_handler = RssHandler::new(
-                feed,
-                Duration::hours(self.rss_feed_backdate_hours as i64),
-                Arc::clone(&database),
-                Arc::new(reqwest::Client::new()),
-            );
+        if self.dry_run {
+            info!("Running in dry-run mode, nothing will be posted");
+        }
 
+        let mut handles = Vec::with_capacity(feeds.len() + 1);
+        if !self.dry_run {
             handles.push(tokio::spawn({
-                let database = database.clone();
-                let bsky_handler = bsky_client.clone();
-                let post_languages = self.post_languages.clone();
-                let og_description_selector = og_description_selector.clone();
-                let og_image_selector = og_image_selector.clone();
+                let outbox = Arc::clone(&outbox);
+                let bsky_clients = Arc::clone(&bsky_clients);
+                let mut shutdown = shutdown.clone();
                 async move {
-                    loop {
-                        bsky_handler.sync_session().await.unwrap();
-                        info!(
-                            "Checking for unposted entries for RSS feed: {}",
-                            rss_handler.feed_url()
+                    while !shutdown.is_triggered() {
+                        if let Err(err) = outbox.retry_due(&bsky_clients, &shutdown).await {
+                            error!("Failed to retry posts in the outbox: {err:?}");
+                        }
+                        tokio::select! {
+                            _ = sleep(OUTBOX_POLL_INTERVAL) => {}
+                            _ = shutdown.wait() => {}
+                        }
+                    }
+                }
+            }));
+        }
+        for feed in feeds {
+            let http_client = Arc::new(reqwest::Client::new());
+            let worker = FeedWorker {
+                composer: PostComposer::new(&feed.post_template)
+                    .with_context(|| format!("invalid post template for feed {}", feed.url))?,
+                rss_handler: RssHandler::new(
+                    &feed,
+                    EntryFilter::new(&blocklist, &feed.include, &feed.exclude)
+                        .with_context(|| format!("invalid filter rules for feed {}", feed.url))?,
+                    Arc::clone(&database),
+                    Arc::clone(&http_client),
+                    self.dry_run,
+                ),
+                http_client,
+                database: Arc::clone(&database),
+                outbox: Arc::clone(&outbox),
+                rate_limiter: Arc::clone(&rate_limiter),
+                bsky_clients: feed
+                    .accounts
+                    .iter()
+                    .map(|name| (name.clone(), Arc::clone(&bsky_clients[name])))
+                    .collect(),
+                dry_run: self.dry_run,
+                dry_run_posted: HashSet::new(),
+                feed,
+            };
+            handles.push(tokio::spawn(worker.run(shutdown.clone())));
+        }
+
+        // Run until a task fails or a shutdown signal is received.
+        tokio::select! {
+            result = future::try_join_all(handles.iter_mut()) => {
+                result?;
+                return Ok(());
+            }
+            result = shutdown::wait_for_signal() => result?,
+        }
+
+        // Stop starting new work and give in-flight posts a chance to finish.
+        info!(
+            "Shutting down, waiting up to {} seconds for in-flight posts to finish",
+            self.shutdown_timeout_seconds
+        );
+        shutdown_trigger.trigger();
+        if tokio::time::timeout(
+            std::time::Duration::from_secs(self.shutdown_timeout_seconds),
+            future::join_all(handles),
+        )
+        .await
+        .is_err()
+        {
+            warn!("Timed out waiting for in-flight posts to finish");
+        }
+        for (name, bsky_client) in bsky_clients.iter() {
+            if let Err(err) = bsky_client.sync_session().await {
+                warn!("Failed to sync session for account '{name}': {err:?}");
+            }
+        }
+        info!("Shutdown complete");
+
+        Ok(())
+    }
+}
+
+/// Store the canonicalized key of URLs that were posted before keys were stored with them.
+///
+/// URLs are canonicalized with the settings of the feed they were posted from. URLs from an unknown feed
+/// use the settings shared by every feed, or the default tracking parameters if the feeds differ.
+async fn backfill_url_keys(database: &Database, feeds: &[FeedOptions]) -> Result<()> {
+    let urls = database.get_urls_without_key().await?;
+    if urls.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "Storing canonicalized keys for {} previously posted URL(s)",
+        urls.len()
+    );
+    let shared = feeds.first().filter(|first| {
+        feeds.iter().all(|feed| {
+            feed.canonicalize_urls == first.canonicalize_urls
+                && feed.tracking_params == first.tracking_params
+        })
+    });
+    for (url, feed_url) in urls {
+        let feed = feed_url
+            .and_then(|feed_url| feeds.iter().find(|feed| feed.url.as_str() == feed_url))
+            .or(shared);
+        let url_key = match feed {
+            Some(feed) if !feed.canonicalize_urls => url.clone(),
+            Some(feed) => canonicalize_url(&url, &feed.tracking_params),
+            None => canonicalize_url(&url, DEFAULT_TRACKING_PARAMS),
+        };
+        database.set_url_key(&url, &url_key).await?;
+    }
+    Ok(())
+}
+
+/// Checks a single feed for new entries on an interval and posts them.
+struct FeedWorker {
+    feed: FeedOptions,
+    composer: PostComposer,
+    rss_handler: RssHandler,
+    http_client: Arc<reqwest::Client>,
+    database: Arc<Database>,
+    outbox: Arc<Outbox>,
+    rate_limiter: Arc<RateLimiter>,
+    bsky_clients: Vec<(String, Arc<BlueskyClient>)>,
+    dry_run: bool,
+    /// Links that have already been printed in dry-run mode, so they are only printed once.
+    dry_run_posted: HashSet<String>,
+}
+
+impl FeedWorker {
+    async fn run(mut self, mut shutdown: Shutdown) {
+        while !shutdown.is_triggered() {
+            for (name, bsky_client) in &self.bsky_clients {
+                if let Err(err) = bsky_client.sync_session().await {
+                    warn!("Failed to sync session for account '{name}': {err:?}");
+                }
+            }
+            self.check_feed(&shutdown).await;
+
+            // Wait interval time for next iteration.
+            info!(
+                "Now waiting for {} seconds before re-running",
+                self.feed.rerun_interval_seconds
+            );
+            tokio::select! {
+                _ = sleep(std::time::Duration::from_secs(self.feed.rerun_interval_seconds)) => {}
+                _ = shutdown.wait() => {}
+            }
+        }
+    }
+
+    async fn check_feed(&mut self, shutdown: &Shutdown) {
+        info!(
+            "Checking for unposted entries for RSS feed: {}",
+            self.rss_handler.feed_url()
+        );
+        let rss_feed = match self.rss_handler.fetch_unposted().await {
+            Ok(Some(rss_feed)) => rss_feed,
+            Ok(None) => {
+                info!(
+                    "Feed {} has not been modified since the last check",
+                    self.rss_handler.feed_url()
+                );
+                return;
+            }
+            Err(err) => {
+                error!(
+                    "Failed to fetch feed {}: {err:?} - skipping for this iteration",
+                    self.rss_handler.feed_url()
+                );
+                return;
+            }
+        };
+
+        // Whether any entries need to be retried on the next interval.
+        let mut retry_entries = false;
+        for post in &rss_feed.entries {
+            // Leave the remaining entries for the next run.
+            if shutdown.is_triggered() {
+                info!(
+                    "Shutting down, skipping remaining entries for feed {}",
+                    self.rss_handler.feed_url()
+                );
+                return;
+            }
+            if !self.post_entry(&rss_feed.feed, post).await {
+                retry_entries = true;
+            }
+        }
+        for post in &rss_feed.updated_entries {
+            if shutdown.is_triggered() {
+                info!(
+                    "Shutting down, skipping remaining entries for feed {}",
+                    self.rss_handler.feed_url()
+                );
+                return;
+            }
+            if !self.update_entry(&rss_feed.feed, post).await {
+                retry_entries = true;
+            }
+        }
+
+        if self.feed.retract_missing_entries
+            && let Err(err) = self
+                .retract_missing_entries(&rss_feed.links, &rss_feed.entry_ids)
+                .await
+        {
+            error!(
+                "Failed to check feed {} for removed entries: {err:?}",
+                self.rss_handler.feed_url()
+            );
+        }
+
+        // Dry runs leave the database untouched so that a real run still sees every entry.
+        if self.dry_run {
+            return;
+        }
+
+        // Only allow conditional requests once every entry has been handled.
+        if !retry_entries && let Err(err) = self.rss_handler.save_validators().await {
+            warn!(
+                "Failed to store cache validators for feed {}: {err}",
+                self.rss_handler.feed_url()
+            );
+        }
+
+        // Remove old posts from the database.
+        if let Err(err) = self.database.remove_old_stored_posts().await {
+            warn!("Failed to run query to remove old stored posts {err}");
+        }
+        if let Err(err) = self.database.remove_old_seen_entries().await {
+            warn!("Failed to run query to remove old seen entries {err}");
+        }
+        if let Err(err) = self.database.remove_old_skipped_entries().await {
+            warn!("Failed to run query to remove old skipped entries {err}");
+        }
+        if let Err(err) = self
+            .database
+            .remove_old_post_log_entries(Utc::now() - Duration::days(1))
+            .await
+        {
+            warn!("Failed to run query to remove old post log entries {err}");
+        }
+    }
+
+    /// Post an entry to every account of the feed.
+    ///
+    /// Returns false if the entry could not be handled and should be retried next interval.
+    async fn post_entry(&mut self, feed: &Feed, post: &UnpostedEntry) -> bool {
+        if self.dry_run && self.dry_run_posted.contains(&post.link) {
+            return true;
+        }
+        info!("Running for post '{}'", post.link);
+        let post_data = match self.compose_entry(feed, post).await {
+            Composed::Post(post_data) => *post_data,
+            Composed::Retry => return false,
+            Composed::Unpostable(err) => {
+                if let Err(err) = self
+                    .rss_handler
+                    .skip_unpostable(post, &format!("{err:#}"))
+                    .await
+                {
+                    error!("Failed to store '{}' as skipped: {err:?}", post.link);
+                }
+                return true;
+            }
+        };
+
+        if self.dry_run {
+            let accounts = self
+                .bsky_clients
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            match describe_post(&post_data, true).await {
+                Ok(description) => println!(
+                    "[dry-run] Would post '{}' to account(s) {accounts}:\n{description}",
+                    post.link
+                ),
+                Err(err) => error!("Failed to describe post for {}: {err:?}", post.link),
+            }
+            self.dry_run_posted.insert(post.link.clone());
+            return true;
+        }
+
+        // Post the resulting data to every account, queueing it for later if that fails, the account
+        // has reached its posting limits, or posts from other feeds are already queued for it.
+        let feed_url = Some(self.feed.url.as_str());
+        let mut records = Vec::new();
+        for (name, bsky_client) in &self.bsky_clients {
+            match self.database.has_pending_outbox_entries(name).await {
+                Ok(false) => {}
+                Ok(true) => {
+                    if let Err(err) = self
+                        .outbox
+                        .queue(&post.link, name, feed_url, Some(post.date), &post_data)
+                        .await
+                    {
+                        error!("Failed to add '{}' to the outbox: {err:?}", post.link);
+                    }
+                    continue;
+                }
+                Err(err) => warn!("Failed to check the outbox of account '{name}': {err:?}"),
+            }
+            let result = match self.rate_limiter.acquire(name, feed_url).await {
+                Ok(Slot::Available(_slot)) => {
+                    debug!("Posting '{}' to account '{name}'", post.link);
+                    let result = bsky_client.post(&post_data).await;
+                    if result.is_ok()
+                        && let Err(err) = self.rate_limiter.record(name, feed_url).await
+                    {
+                        warn!(
+                            "Failed to record post '{}' for account '{name}': {err:?}",
+                            post.link
                         );
+                    }
+                    result
+                }
+                Ok(Slot::Deferred(next_slot)) => {
+                    if let Err(err) = self
+                        .outbox
+                        .defer(
+                            &post.link,
+                            name,
+                            feed_url,
+                            Some(post.date),
+                            &post_data,
+                            next_slot,
+                        )
+                        .await
+                    {
+                        error!("Failed to add '{}' to the outbox: {err:?}", post.link);
+                    }
+                    continue;
+                }
+                Err(err) => Err(err.context("failed to check posting limits")),
+            };
+            match result {
+                Ok(thread) => records.extend(PostRecord::from_thread(&post.link, name, thread)),
+                Err(err) => {
+                    error!(
+                        "Failed to post '{}' to account '{name}': {err:?}",
+                        post.link
+                    );
+                    if let Err(err) = self
+                        .outbox
+                        .enqueue(
+                            &post.link,
+                            name,
+                            feed_url,
+                            Some(post.date),
+                            &post_data,
+                            &err,
+                        )
+                        .await
+                    {
+                        error!("Failed to add '{}' to the outbox: {err:?}", post.link);
+                    }
+                }
+            }
+        }
+
+        // The entry has either been posted or queued, so mark it as handled.
+        let posted = PostedUrl {
+            url: post.link.clone(),
+            url_key: Some(post.url_key.clone()),
+            feed_url: Some(self.feed.url.to_string()),
+            entry_id: Some(post.entry.id.clone()),
+            title: post
+                .entry
+                .title
+                .as_ref()
+                .map(|title| title.content.trim().to_string()),
+            posted_at: Some(Utc::now()),
+            content_hash: Some(content_hash(&post.entry, &post.link)),
+            entry_updated_at: post.entry.updated,
+            retracted_at: None,
+        };
+        if let Err(err) = self.database.insert_posted_entry(&posted).await {
+            error!("Failed to store '{}' in the database: {err:?}", post.link);
+            return true;
+        }
+        for record in &records {
+            if let Err(err) = self.database.insert_post_record(record).await {
+                error!(
+                    "Failed to store post record {} for '{}' in the database: {err:?}",
+                    record.uri, post.link
+                );
+            }
+        }
+        true
+    }
+    /// Bring the existing posts of an entry that has changed since it was posted up to date.
+    ///
+    /// Returns false if the entry could not be handled and should be retried next interval.
+    async fn update_entry(&mut self, feed: &Feed, post: &UnpostedEntry) -> bool {
+        if self.dry_run && self.dry_run_posted.contains(&post.link) {
+            return true;
+        }
+        info!("Entry '{}' has changed since it was posted", post.link);
+        let post_data = match self.compose_entry(feed, post).await {
+            Composed::Post(post_data) => *post_data,
+            Composed::Retry => return false,
+            // The existing posts are left as they are until the entry changes again.
+            Composed::Unpostable(_) => return true,
+        };
 
-                        match rss_handler.fetch_unposted().await { Ok(rss_feed) => {
-                            for post in rss_feed.entries {
-                                let Some(post_link) = post.links.first() else {
-                                    debug!(
-                                        "Post '{:?}' did not have any links attached, it will be skipped.",
-                                        post.title
-                                    );
-                                    continue;
-                                };
-                                info!("Running for post '{}'", post_link.href);
-
-                                // Fetch the page text to extract opengraph data.
-                                let page = match reqwest::get(&post_link.href).await {
-                                    Ok(res) => res.text().await.context("failed to page text").unwrap(),
-                                    Err(err) => {
-                                        error!(
-                                            "Failed to get page data for {}: {err} - it will be retried next interval",
-                                            &post_link.href
-                                        );
-                                        continue;
-                                    }
-                                };
-
-                                // Synchronously obtain data from the page so that we do not carry `html` across an await point.
-                                let post_data = {
-                                    let html = scraper::Html::parse_document(&page);
-                                    PostData {
-                                        created_at: post.published.unwrap_or(DateTime::default()),
-                                        text: format!(
-                                            "{} - {}",
-                                            post.title
-                                                .clone()
-                                                .map_or(String::from("New post"), |f| f.content.trim().to_string()),
-                                            post_link.href
-                                        ),
-                                        languages: post_languages.clone(),
-                                        embed: Some(PostEmbed {
-                                            title: post
-                                                .title
-                                                .map(|f| f.content.trim().to_string())
-                                                .unwrap_or_else(|| post_link.href.clone()),
-                                            description: post
-                                                .summary
-                                                .map(|summary| {
-                                                    Html::parse_fragment(&summary.content)
-                                                        .tree
-                                                        .into_iter()
-                                                        .filter_map(|node| {
-                                                            node.as_text()
-                                                                .map(|text| text.text.trim().to_string())
-                                                        })
-                                                        .collect::<String>()
-                                                })
-                                                .or_else(|| {
-                                                    html.select(&og_description_selector)
-                                                        .next()
-                                                        .and_then(|desc| {
-                                                            desc.value()
-                                                                .attr("content")
-                                                                .map(|a| a.trim().to_string())
-                                                        })
-                                                })
-                                                .unwrap_or_else(|| {
-                                                    "This site has not provided a description"
-                                                        .into()
-                                                }),
-                                            thumbnail_url: html
-                                                .select(&og_image_selector)
-                                                .next()
-                                                .and_then(|f| f.value().attr("content"))
-                                                .and_then(|u| Url::parse(u).ok()),
-                                            uri: Url::parse(&post_link.href).unwrap(),
-                                        }),
-                                    }
-                                };
-
-                                // Post the resulting data to Bluesky and add it to the database if successful.
-                                bsky_handler.post(post_data).await.unwrap();
-                                database
-                                    .insert_posted_url(post_link.href.as_str())
-                                    .await
-                                    .expect("post URL insert into database should not fail");
-                            }
-
-                            // Remove old posts from the database.
-                            if let Err(err) = database.remove_old_stored_posts().await {
-                                warn!("Failed to run query to remove old stored posts {err}");
-                            }
-                        } _ => {
-                            error!(
-                                "Failed to fetch feed {}: skipping for this iteration",
-                                rss_handler.feed_url()
-                            );
-                        }};
-
-                        // Wait interval time for next iteration.
-                        info!(
-                            "Now waiting for {} seconds before re-running",
-                            self.run_interval_seconds
+        if self.dry_run {
+            match describe_post(&post_data, true).await {
+                Ok(description) => println!(
+                    "[dry-run] Would update existing posts of '{}' ({:?}):\n{description}",
+                    post.link, self.feed.update_mode
+                ),
+                Err(err) => error!("Failed to describe post for {}: {err:?}", post.link),
+            }
+            self.dry_run_posted.insert(post.link.clone());
+            return true;
+        }
+
+        // The entry may have been posted under a different form of its link.
+        let posted_url = post.posted_url.as_deref().unwrap_or(&post.link);
+        let records = match self.database.get_post_records_for_url(posted_url).await {
+            Ok(records) => records,
+            Err(err) => {
+                error!("Failed to get post records for '{}': {err:?}", post.link);
+                return false;
+            }
+        };
+        let mut updated = true;
+        for record in records.iter().filter(|record| record.root_uri.is_none()) {
+            let Some((_, bsky_client)) = self
+                .bsky_clients
+                .iter()
+                .find(|(name, _)| name == &record.account)
+            else {
+                warn!(
+                    "Post record {} belongs to account '{}' which is not used by this feed, skipping",
+                    record.uri, record.account
+                );
+                continue;
+            };
+            let reply_uris = records
+                .iter()
+                .filter(|reply| reply.root_uri.as_ref() == Some(&record.uri))
+                .map(|reply| reply.uri.clone())
+                .collect::<Vec<_>>();
+            let result = match self.feed.update_mode {
+                UpdateMode::Off => continue,
+                UpdateMode::Edit => {
+                    bsky_client
+                        .edit_post(&record.uri, &reply_uris, &post_data)
+                        .await
+                }
+                UpdateMode::Repost => {
+                    bsky_client
+                        .repost(&record.uri, &reply_uris, &post_data)
+                        .await
+                }
+            };
+            match result {
+                Ok(thread) => {
+                    let thread = PostRecord::from_thread(posted_url, &record.account, thread);
+                    if let Err(err) = self
+                        .database
+                        .replace_thread_records(&record.uri, &thread)
+                        .await
+                    {
+                        error!(
+                            "Failed to store post records for '{}' in the database: {err:?}",
+                            post.link
                         );
-                        sleep(std::time::Duration::from_secs(self.run_interval_seconds)).await;
                     }
                 }
-            }));
+                Err(err) => {
+                    error!(
+                        "Failed to update post {} for '{}': {err:?} - it will be retried next interval",
+                        record.uri, post.link
+                    );
+                    updated = false;
+                }
+            }
         }
+        if !updated {
+            return false;
+        }
+
+        if let Err(err) = self
+            .database
+            .update_posted_entry_version(
+                posted_url,
+                post.entry.title.as_ref().map(|title| title.content.trim()),
+                &content_hash(&post.entry, &post.link),
+                post.entry.updated,
+            )
+            .await
+        {
+            error!("Failed to store '{}' in the database: {err:?}", post.link);
+        }
+        true
+    }
+
+    /// Delete the posts of recently posted entries that have been removed from the feed.
+    ///
+    /// To stop a truncated feed response from deleting every post, entries are only counted as missing
+    /// while the feed still contains an entry that was posted before them, and nothing is counted when
+    /// most of the recently posted entries are missing at once.
+    async fn retract_missing_entries(
+        &mut self,
+        links: &HashSet<String>,
+        entry_ids: &HashSet<String>,
+    ) -> Result<()> {
+        if links.is_empty() {
+            return Ok(());
+        }
+        let since = Utc::now() - Duration::hours(i64::from(self.feed.retract_window_hours));
+        let (present, missing): (Vec<_>, Vec<_>) = self
+            .database
+            .get_retraction_candidates(self.feed.url.as_str(), since)
+            .await?
+            .into_iter()
+            .partition(|candidate| {
+                // An entry whose link has changed is still in the feed under its ID.
+                links.contains(&candidate.url)
+                    || candidate
+                        .url_key
+                        .as_ref()
+                        .is_some_and(|url_key| links.contains(url_key))
+                    || candidate
+                        .entry_id
+                        .as_ref()
+                        .is_some_and(|entry_id| entry_ids.contains(entry_id))
+            });
 
-        future::try_join_all(handles).await?;
+        // Entries posted before the oldest entry still in the feed may have just fallen off the end of it.
+        let Some(oldest_present) = present.iter().map(|candidate| candidate.posted_at).min() else {
+            return Ok(());
+        };
+        let missing = missing
+            .into_iter()
+            .filter(|candidate| candidate.posted_at >= oldest_present)
+            .collect::<Vec<_>>();
 
+        if !self.dry_run {
+            for candidate in present.iter().filter(|c| c.missing_fetches > 0) {
+                self.database.set_missing_fetches(&candidate.url, 0).await?;
+            }
+        }
+        if missing.len() > 1 && missing.len() * 2 > present.len() + missing.len() {
+            warn!(
+                "{} of {} recently posted entries are missing from feed {}, not retracting anything in case the feed is incomplete",
+                missing.len(),
+                present.len() + missing.len(),
+                self.feed.url
+            );
+            return Ok(());
+        }
+
+        for candidate in missing {
+            let missing_fetches = candidate.missing_fetches + 1;
+            let gone = is_link_gone(&self.http_client, &candidate.url)
+                .await
+                .unwrap_or_else(|err| {
+                    warn!(
+                        "Failed to check whether '{}' still exists: {err}",
+                        candidate.url
+                    );
+                    false
+                });
+            if !gone && missing_fetches < i64::from(self.feed.retract_after_missing_fetches) {
+                info!(
+                    "Entry '{}' has been missing from feed {} for {missing_fetches} fetch(es)",
+                    candidate.url, self.feed.url
+                );
+                if !self.dry_run {
+                    self.database
+                        .set_missing_fetches(&candidate.url, missing_fetches)
+                        .await?;
+                }
+                continue;
+            }
+            self.retract_entry(&candidate.url).await?;
+        }
         Ok(())
     }
+
+    /// Delete every post of an entry that has been removed from the feed and mark it as retracted.
+    ///
+    /// Nothing is deleted unless every post belongs to an account used by this feed. A failed delete
+    /// leaves the entry unretracted so that it is retried next interval, which is safe as deleting a
+    /// post that no longer exists succeeds.
+    async fn retract_entry(&mut self, url: &str) -> Result<()> {
+        if self.dry_run {
+            println!("[dry-run] Would delete the posts of '{url}' as it was removed from its feed");
+            return Ok(());
+        }
+        info!("Entry '{url}' has been removed from its feed, deleting its posts");
+
+        let mut deletions = Vec::new();
+        for record in self.database.get_post_records_for_url(url).await? {
+            let Some((_, bsky_client)) = self
+                .bsky_clients
+                .iter()
+                .find(|(name, _)| name == &record.account)
+            else {
+                warn!(
+                    "Post record {} belongs to account '{}' which is not used by this feed, not retracting '{url}'",
+                    record.uri, record.account
+                );
+                return Ok(());
+            };
+            deletions.push((record, Arc::clone(bsky_client)));
+        }
+        for (record, bsky_client) in &deletions {
+            if let Err(err) = bsky_client.delete_post(&record.uri).await {
+                error!(
+                    "Failed to delete post {} for '{url}': {err:?} - it will be retried next interval",
+                    record.uri
+                );
+                return Ok(());
+            }
+        }
+        self.database.delete_outbox_entries_for_url(url).await?;
+        self.database.mark_retracted(url, Utc::now()).await
+    }
+
+    /// Fetch the page an entry links to and build the post data for it.
+    async fn compose_entry(&self, feed: &Feed, post: &UnpostedEntry) -> Composed {
+        // Fetch the page text to extract opengraph data.
+        let page = match fetch_page(&post.link).await {
+            Ok(page) => page,
+            Err(err) => {
+                error!(
+                    "Failed to get page data for {}: {err} - it will be retried next interval",
+                    &post.link
+                );
+                return Composed::Retry;
+            }
+        };
+
+        match self.composer.compose(feed, post, &page, &self.feed) {
+            Ok(post_data) => Composed::Post(Box::new(post_data)),
+            Err(err) => {
+                error!(
+                    "Failed to compose post for {}: {err:?} - it will be skipped until it or the post template changes",
+                    &post.link
+                );
+                Composed::Unpostable(err)
+            }
+        }
+    }
+}
+
+/// The result of building the post data for an entry.
+enum Composed {
+    Post(Box<PostData>),
+    /// Building the post failed in a way that may succeed next interval.
+    Retry,
+    /// The post can never be built from the entry as it is, such as when its link is invalid or
+    /// the post template fails to render.
+    Unpostable(anyhow::Error),
 }
@@ -0,0 +1,619 @@
+use crate::bsky::{
+    MAX_ALT_TEXT_GRAPHEMES, MAX_POST_GRAPHEMES, MAX_POST_IMAGES, MAX_POST_TAGS, MAX_TAG_GRAPHEMES,
+    MAX_VIDEO_ALT_TEXT_GRAPHEMES, MAX_VIDEO_BYTES, MAX_VIDEO_DURATION, PostData, PostEmbed,
+    PostImage, PostVideo,
+};
+use crate::config::{CategoryTags, EmbedMode, FeedOptions};
+use crate::image::ImageOptions;
+use crate::rss::UnpostedEntry;
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use feed_rs::model::{Category, Entry, Feed};
+use minijinja::{Environment, Error, ErrorKind};
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::Serialize;
+use std::fmt::Write;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The text used to mark that text has been shortened.
+const ELLIPSIS: &str = "…";
+
+/// The maximum number of replies a thread of entry content can have.
+const MAX_THREAD_REPLIES: usize = 10;
+
+/// The template used to generate post text when a feed does not provide its own.
+pub const DEFAULT_POST_TEMPLATE: &str = r#"{{ title or "New post" }} - {{ link }}"#;
+
+/// The values made available to post templates.
+#[derive(Debug, Serialize)]
+struct TemplateContext<'a> {
+    title: Option<String>,
+    link: &'a str,
+    author: Option<&'a str>,
+    authors: Vec<&'a str>,
+    categories: Vec<&'a str>,
+    date: String,
+    published: Option<String>,
+    updated: Option<String>,
+    summary: Option<String>,
+    feed_title: Option<String>,
+}
+
+/// Builds post data from feed entries and the pages they link to.
+pub struct PostComposer {
+    env: Environment<'static>,
+    og_description_selector: Selector,
+    og_image_selector: Selector,
+    img_selector: Selector,
+}
+
+impl PostComposer {
+    pub fn new(post_template: &str) -> Result<Self> {
+        let mut env = Environment::new();
+        env.add_filter("truncate", truncate_filter);
+        env.add_filter("lowercase", |value: &str| value.to_lowercase());
+        env.add_filter("uppercase", |value: &str| value.to_uppercase());
+        env.add_filter("date", date_filter);
+        env.set_formatter(|out, state, value| {
+            // Render missing values as nothing instead of "none".
+            if value.is_none() || value.is_undefined() {
+                return Ok(());
+            }
+            minijinja::escape_formatter(out, state, value)
+        });
+        env.add_template_owned("post", post_template.to_string())
+            .context("failed to parse post template")?;
+
+        Ok(Self {
+            env,
+            og_description_selector: Selector::parse(r#"meta[property="og:description"]"#)
+                .expect("selector expression should be parseable"),
+            og_image_selector: Selector::parse(r#"meta[property="og:image"]"#)
+                .expect("selector expression should be parseable"),
+            img_selector: Selector::parse("img[src]")
+                .expect("selector expression should be parseable"),
+        })
+    }
+
+    /// Render the post text for an entry using the post template.
+    ///
+    /// Text that is longer than the post length limit is shortened by truncating the title first,
+    /// and then by truncating the text itself without cutting through any links.
+    pub fn render_text(&self, feed: &Feed, unposted: &UnpostedEntry) -> Result<String> {
+        let entry = &unposted.entry;
+        let mut context = TemplateContext {
+            title: entry.title.as_ref().map(|f| f.content.trim().to_string()),
+            link: &unposted.link,
+            author: entry.authors.first().map(|person| person.name.as_str()),
+            authors: entry
+                .authors
+                .iter()
+                .map(|person| person.name.as_str())
+                .collect(),
+            categories: entry
+                .categories
+                .iter()
+                .map(|category| category.label.as_deref().unwrap_or(&category.term))
+                .collect(),
+            date: unposted.date.to_rfc3339(),
+            published: entry.published.map(|date| date.to_rfc3339()),
+            updated: entry.updated.map(|date| date.to_rfc3339()),
+            summary: entry
+                .summary
+                .as_ref()
+                .map(|summary| html_to_text(&summary.content)),
+            feed_title: feed.title.as_ref().map(|f| f.content.trim().to_string()),
+        };
+
+        let mut text = self.render_template(&context)?;
+        loop {
+            let overflow = text
+                .graphemes(true)
+                .count()
+                .saturating_sub(MAX_POST_GRAPHEMES);
+            let Some(title) = context.title.as_deref().filter(|_| overflow > 0) else {
+                break;
+            };
+            let keep = title.graphemes(true).count().saturating_sub(overflow);
+            if keep == 0 {
+                break;
+            }
+            context.title = Some(truncate_graphemes(title, keep, ELLIPSIS));
+            text = self.render_template(&context)?;
+        }
+        Ok(truncate_outside_links(&text, MAX_POST_GRAPHEMES))
+    }
+
+    fn render_template(&self, context: &TemplateContext) -> Result<String> {
+        let text = self
+            .env
+            .get_template("post")
+            .expect("post template should always be registered")
+            .render(context)
+            .context("failed to render post template")?;
+        Ok(text.trim().to_string())
+    }
+
+    /// Build the post data for an entry from the entry itself and the HTML of the page it links to.
+    pub fn compose(
+        &self,
+        feed: &Feed,
+        unposted: &UnpostedEntry,
+        page: &str,
+        options: &FeedOptions,
+    ) -> Result<PostData> {
+        let entry = &unposted.entry;
+        let link = unposted.link.as_str();
+        let html = Html::parse_document(page);
+        let tags = if options.category_tags == CategoryTags::Off {
+            Vec::new()
+        } else {
+            category_tags(entry, options)
+        };
+        let mut text = self.render_text(feed, unposted)?;
+        if options.category_tags.hashtags() {
+            text = append_hashtags(&text, &tags);
+        }
+        Ok(PostData {
+            created_at: unposted.date,
+            text,
+            tags: if options.category_tags.tags() {
+                tags.into_iter().take(MAX_POST_TAGS).collect()
+            } else {
+                Vec::new()
+            },
+            replies: if options.thread_entries {
+                thread_replies(entry)
+            } else {
+                Vec::new()
+            },
+            languages: options.post_languages.clone(),
+            disable_comments: options.disable_post_comments,
+            images: match options.embed_mode {
+                EmbedMode::Images => self.entry_images(entry, link),
+                EmbedMode::Link | EmbedMode::Video => Vec::new(),
+            },
+            video: match options.embed_mode {
+                EmbedMode::Video => entry_video(entry),
+                EmbedMode::Link | EmbedMode::Images => None,
+            },
+            image_options: ImageOptions {
+                max_bytes: options.max_image_bytes,
+                thumbnail_fit: options.thumbnail_fit,
+                thumbnail_ratio: options.thumbnail_ratio,
+                ..Default::default()
+            },
+            embed: Some(PostEmbed {
+                title: entry
+                    .title
+                    .as_ref()
+                    .map(|f| f.content.trim().to_string())
+                    .unwrap_or_else(|| link.to_string()),
+                description: entry
+                    .summary
+                    .as_ref()
+                    .map(|summary| html_to_text(&summary.content))
+                    .or_else(|| {
+                        html.select(&self.og_description_selector)
+                            .next()
+                            .and_then(|desc| {
+                                desc.value().attr("content").map(|a| a.trim().to_string())
+                            })
+                    })
+                    .unwrap_or_else(|| "This site has not provided a description".into()),
+                thumbnail_url: html
+                    .select(&self.og_image_selector)
+                    .next()
+                    .and_then(|f| f.value().attr("content"))
+                    .and_then(|u| Url::parse(u).ok()),
+                uri: Url::parse(link).context("entry link should be a valid url")?,
+            }),
+        })
+    }
+
+    /// Collect the images of an entry from its media and enclosures, followed by the `<img>` tags in
+    /// its content, up to the number of images a post can have.
+    fn entry_images(&self, entry: &Entry, link: &str) -> Vec<PostImage> {
+        let mut images: Vec<PostImage> = Vec::new();
+        let mut add_image = |url: Url, alt: Option<String>| {
+            if images.len() < MAX_POST_IMAGES
+                && matches!(url.scheme(), "http" | "https")
+                && !images.iter().any(|image| image.url == url)
+            {
+                let alt = alt.unwrap_or_default();
+                images.push(PostImage {
+                    url,
+                    alt: truncate_graphemes(alt.trim(), MAX_ALT_TEXT_GRAPHEMES, ELLIPSIS),
+                });
+            }
+        };
+
+        for media in &entry.media {
+            let alt = media
+                .description
+                .as_ref()
+                .or(media.title.as_ref())
+                .map(|text| html_to_text(&text.content));
+            for content in &media.content {
+                let Some(url) = &content.url else {
+                    continue;
+                };
+                let is_image = match &content.content_type {
+                    Some(content_type) => content_type.ty() == "image",
+                    None => has_image_extension(url),
+                };
+                if is_image {
+                    add_image(url.clone(), alt.clone());
+                }
+            }
+        }
+
+        let base_url = Url::parse(link).ok();
+        let bodies = [
+            entry
+                .content
+                .as_ref()
+                .and_then(|content| content.body.as_deref()),
+            entry
+                .summary
+                .as_ref()
+                .map(|summary| summary.content.as_str()),
+        ];
+        for body in bodies.into_iter().flatten() {
+            for image in Html::parse_fragment(body).select(&self.img_selector) {
+                let Some(url) = image
+                    .value()
+                    .attr("src")
+                    .and_then(|src| Url::options().base_url(base_url.as_ref()).parse(src).ok())
+                else {
+                    continue;
+                };
+                add_image(url, image.value().attr("alt").map(str::to_string));
+            }
+        }
+        images
+    }
+}
+
+/// Find the first MP4 video in the media of an entry that is within the size and duration limits.
+///
+/// Videos without a known size or duration are still used, and are checked against both limits
+/// when they are downloaded.
+fn entry_video(entry: &Entry) -> Option<PostVideo> {
+    entry.media.iter().find_map(|media| {
+        let content = media.content.iter().find(|content| {
+            let is_mp4 = match &content.content_type {
+                Some(content_type) => content_type.essence().to_string() == "video/mp4",
+                None => content
+                    .url
+                    .as_ref()
+                    .is_some_and(|url| url.path().to_lowercase().ends_with(".mp4")),
+            };
+            is_mp4
+                && content.url.is_some()
+                && content.size.is_none_or(|size| size <= MAX_VIDEO_BYTES)
+                && content
+                    .duration
+                    .or(media.duration)
+                    .is_none_or(|duration| duration <= MAX_VIDEO_DURATION)
+        })?;
+        let alt = media
+            .description
+            .as_ref()
+            .or(media.title.as_ref())
+            .or(entry.title.as_ref())
+            .map(|text| html_to_text(&text.content))
+            .unwrap_or_default();
+        Some(PostVideo {
+            url: content.url.clone()?,
+            alt: truncate_graphemes(&alt, MAX_VIDEO_ALT_TEXT_GRAPHEMES, ELLIPSIS),
+            width: content.width,
+            height: content.height,
+        })
+    })
+}
+
+/// Check whether a URL points to an image based on its file extension.
+fn has_image_extension(url: &Url) -> bool {
+    let path = url.path().to_lowercase();
+    [".jpg", ".jpeg", ".png", ".webp", ".gif", ".avif"]
+        .iter()
+        .any(|extension| path.ends_with(extension))
+}
+
+/// Turn the categories of an entry into tags, following the feed's allow, deny and rename settings.
+///
+/// Categories are matched case-insensitively by either their term or label, and are renamed before
+/// being turned into tags. Tags only keep letters, numbers and underscores so they work as hashtags.
+fn category_tags(entry: &Entry, options: &FeedOptions) -> Vec<String> {
+    let matches = |list: &[String], category: &Category| {
+        list.iter().any(|name| {
+            name.eq_ignore_ascii_case(&category.term)
+                || category
+                    .label
+                    .as_ref()
+                    .is_some_and(|label| name.eq_ignore_ascii_case(label))
+        })
+    };
+
+    let mut tags: Vec<String> = Vec::new();
+    for category in &entry.categories {
+        if options
+            .category_allow
+            .as_ref()
+            .is_some_and(|allow| !matches(allow, category))
+            || matches(&options.category_deny, category)
+        {
+            continue;
+        }
+        let name = options
+            .category_rename
+            .iter()
+            .find(|(from, _)| matches(std::slice::from_ref(from), category))
+            .map_or(category.term.as_str(), |(_, to)| to.as_str());
+        let tag = name
+            .chars()
+            .filter(|char| char.is_alphanumeric() || *char == '_')
+            .collect::<String>();
+
+        // Tags that are only numbers are not recognised as hashtags.
+        if tag.is_empty()
+            || tag.chars().all(|char| char.is_numeric())
+            || tag.graphemes(true).count() > MAX_TAG_GRAPHEMES
+            || tags
+                .iter()
+                .any(|existing| existing.to_lowercase() == tag.to_lowercase())
+        {
+            continue;
+        }
+        tags.push(tag);
+    }
+    tags
+}
+
+/// Add tags to the end of post text as hashtags, skipping any that do not fit or are already in the text.
+fn append_hashtags(text: &str, tags: &[String]) -> String {
+    let mut text = text.to_string();
+    let lowercase = text.to_lowercase();
+    for tag in tags {
+        let hashtag = format!("#{tag}");
+        if lowercase
+            .split_whitespace()
+            .any(|word| word == hashtag.to_lowercase())
+        {
+            continue;
+        }
+        let candidate = format!("{text} {hashtag}");
+        if candidate.graphemes(true).count() <= MAX_POST_GRAPHEMES {
+            text = candidate;
+        }
+    }
+    text
+}
+
+/// Split the content of an entry into replies for a thread, falling back to its summary.
+fn thread_replies(entry: &Entry) -> Vec<String> {
+    let Some(html) = entry
+        .content
+        .as_ref()
+        .and_then(|content| content.body.as_deref())
+        .or(entry
+            .summary
+            .as_ref()
+            .map(|summary| summary.content.as_str()))
+    else {
+        return Vec::new();
+    };
+    let mut replies = split_sentences(&html_to_text(html), MAX_POST_GRAPHEMES);
+    if replies.len() > MAX_THREAD_REPLIES {
+        replies.truncate(MAX_THREAD_REPLIES);
+        if let Some(last) = replies.last_mut() {
+            *last = truncate_graphemes(&format!("{last}{ELLIPSIS}"), MAX_POST_GRAPHEMES, ELLIPSIS);
+        }
+    }
+    replies
+}
+
+/// Split text into chunks of at most `length` graphemes, breaking between sentences where possible.
+///
+/// Sentences that are too long on their own are broken between words, and words that are too long
+/// are broken wherever they need to be.
+pub fn split_sentences(text: &str, length: usize) -> Vec<String> {
+    let pieces = text.split_sentence_bounds().flat_map(|sentence| {
+        if sentence.graphemes(true).count() <= length {
+            vec![sentence]
+        } else {
+            sentence.split_word_bounds().collect()
+        }
+    });
+
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for piece in pieces {
+        let graphemes = piece.graphemes(true).collect::<Vec<_>>();
+        for part in graphemes.chunks(length.max(1)) {
+            if current.graphemes(true).count() + part.len() > length {
+                chunks.push(current.trim().to_string());
+                current.clear();
+            }
+            current.extend(part.iter().copied());
+        }
+    }
+    chunks.push(current.trim().to_string());
+    chunks.retain(|chunk| !chunk.is_empty());
+    chunks
+}
+
+/// Fetch the HTML of the page an entry links to.
+pub async fn fetch_page(url: &str) -> Result<String> {
+    Ok(reqwest::get(url).await?.text().await?)
+}
+
+/// Extract the text from an HTML fragment, collapsing any whitespace.
+pub fn html_to_text(html: &str) -> String {
+    Html::parse_fragment(html)
+        .tree
+        .into_iter()
+        .filter_map(|node| node.as_text().map(|text| text.text.to_string()))
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Shorten text to at most `length` graphemes without cutting through a link, ending it with an ellipsis if it was shortened.
+pub fn truncate_outside_links(text: &str, length: usize) -> String {
+    if text.graphemes(true).count() <= length {
+        return text.to_string();
+    }
+    let keep = length.saturating_sub(ELLIPSIS.graphemes(true).count());
+    let mut cut = text
+        .grapheme_indices(true)
+        .nth(keep)
+        .map_or(text.len(), |(index, _)| index);
+
+    // Move the cut to the start of any link it falls inside of.
+    let word_start = text[..cut].rfind(char::is_whitespace).map_or(0, |index| {
+        index + text[index..].chars().next().map_or(1, char::len_utf8)
+    });
+    let word = &text[word_start..];
+    if word.starts_with("http://") || word.starts_with("https://") {
+        cut = word_start;
+    }
+    format!("{}{ELLIPSIS}", text[..cut].trim_end())
+}
+
+/// Shorten text to at most `length` graphemes, ending it with `end` if it was shortened.
+pub fn truncate_graphemes(text: &str, length: usize, end: &str) -> String {
+    if text.graphemes(true).count() <= length {
+        return text.to_string();
+    }
+    let keep = length.saturating_sub(end.graphemes(true).count());
+    let truncated = text.graphemes(true).take(keep).collect::<String>();
+    format!("{}{end}", truncated.trim_end())
+}
+
+fn truncate_filter(value: &str, length: Option<usize>, end: Option<&str>) -> String {
+    truncate_graphemes(value, length.unwrap_or(255), end.unwrap_or("..."))
+}
+
+/// Format a date, rendering missing dates such as an unset `published` as nothing.
+fn date_filter(value: Option<&str>, format: Option<&str>) -> Result<String, Error> {
+    let Some(value) = value else {
+        return Ok(String::new());
+    };
+    let date = DateTime::parse_from_rfc3339(value).map_err(|err| {
+        Error::new(ErrorKind::InvalidOperation, "value is not a valid date").with_source(err)
+    })?;
+    let mut output = String::new();
+    write!(output, "{}", date.format(format.unwrap_or("%Y-%m-%d")))
+        .map_err(|_| Error::new(ErrorKind::InvalidOperation, "invalid date format string"))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(published: Option<&str>) -> TemplateContext<'static> {
+        TemplateContext {
+            title: Some(String::from("Title")),
+            link: "https://example.com/post",
+            author: None,
+            authors: Vec::new(),
+            categories: Vec::new(),
+            date: String::from("2024-11-10T02:16:40+00:00"),
+            published: published.map(str::to_string),
+            updated: None,
+            summary: None,
+            feed_title: None,
+        }
+    }
+
+    #[test]
+    fn date_filter_formats_dates() {
+        let composer = PostComposer::new("{{ published | date('%d %B') }}").unwrap();
+        let text = composer
+            .render_template(&context(Some("2024-11-10T02:16:40+00:00")))
+            .unwrap();
+        assert_eq!(text, "10 November");
+    }
+
+    #[test]
+    fn date_filter_renders_missing_dates_as_nothing() {
+        let composer = PostComposer::new("({{ published | date('%d %B') }}) {{ link }}").unwrap();
+        let text = composer.render_template(&context(None)).unwrap();
+        assert_eq!(text, "() https://example.com/post");
+    }
+
+    #[test]
+    fn truncate_outside_links_keeps_short_text() {
+        assert_eq!(truncate_outside_links("Short text", 10), "Short text");
+    }
+
+    #[test]
+    fn truncate_outside_links_counts_graphemes() {
+        // Each family emoji is a single grapheme made of several characters.
+        let text = "👨‍👩‍👧".repeat(6);
+        assert_eq!(truncate_outside_links(&text, 6), text);
+        let truncated = truncate_outside_links(&text, 5);
+        assert_eq!(truncated, format!("{}{ELLIPSIS}", "👨‍👩‍👧".repeat(4)));
+        assert_eq!(truncated.graphemes(true).count(), 5);
+    }
+
+    #[test]
+    fn truncate_outside_links_trims_before_the_ellipsis() {
+        assert_eq!(
+            truncate_outside_links("one two three", 9),
+            format!("one two{ELLIPSIS}")
+        );
+    }
+
+    #[test]
+    fn truncate_outside_links_does_not_cut_through_links() {
+        let text = "Read more at https://example.com/a/long/path today";
+        let truncated = truncate_outside_links(text, 30);
+        assert_eq!(truncated, format!("Read more at{ELLIPSIS}"));
+        assert!(truncated.graphemes(true).count() <= 30);
+    }
+
+    #[test]
+    fn truncate_outside_links_keeps_links_before_the_cut() {
+        let text = "https://example.com/post is a post about a very long topic";
+        let truncated = truncate_outside_links(text, 30);
+        assert_eq!(
+            truncated,
+            format!("https://example.com/post is a{ELLIPSIS}")
+        );
+    }
+
+    #[test]
+    fn split_sentences_keeps_sentences_together() {
+        let chunks = split_sentences("First sentence. Second sentence. Third one.", 33);
+        assert_eq!(chunks, ["First sentence. Second sentence.", "Third one."]);
+    }
+
+    #[test]
+    fn split_sentences_breaks_long_sentences_between_words() {
+        let chunks = split_sentences("one two three four five six", 10);
+        assert_eq!(chunks, ["one two", "three four", "five six"]);
+        assert!(
+            chunks
+                .iter()
+                .all(|chunk| chunk.graphemes(true).count() <= 10)
+        );
+    }
+
+    #[test]
+    fn split_sentences_breaks_long_words() {
+        let chunks = split_sentences("abcdefghijklmnopqrstuvwxyz", 10);
+        assert_eq!(chunks, ["abcdefghij", "klmnopqrst", "uvwxyz"]);
+    }
+
+    #[test]
+    fn split_sentences_ignores_empty_text() {
+        assert!(split_sentences("", 10).is_empty());
+        assert!(split_sentences("   ", 10).is_empty());
+    }
+}
@@ -0,0 +1,516 @@
+use crate::bsky::parse_languages;
+use crate::compose::DEFAULT_POST_TEMPLATE;
+use crate::image::ImageOptions;
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use log::debug;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Query parameters removed from links by default when canonicalizing them.
+pub const DEFAULT_TRACKING_PARAMS: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "dclid", "msclkid", "mc_cid", "mc_eid", "_hsenc", "_hsmi",
+    "ref_src",
+];
+
+/// The name of the account configured through command-line arguments.
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+/// The contents of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Settings used by every feed unless the feed overrides them.
+    pub defaults: FeedSettings,
+    /// Named accounts that feeds can post to.
+    pub accounts: BTreeMap<String, AccountConfig>,
+    /// Feeds to check for new posts.
+    pub feeds: Vec<FeedConfig>,
+    /// Rules that skip matching entries from every feed, regardless of the feed's own rules.
+    pub blocklist: Vec<FilterRuleConfig>,
+}
+
+/// A single feed entry in the configuration file.
+///
+/// The settings are read from the same table as the URL. This is done by hand rather than with
+/// `#[serde(flatten)]`, which would silently ignore unknown keys.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub struct FeedConfig {
+    /// The URL pointing directly to the RSS feed.
+    pub url: Url,
+    pub settings: FeedSettings,
+}
+
+impl TryFrom<toml::Table> for FeedConfig {
+    type Error = toml::de::Error;
+
+    fn try_from(mut table: toml::Table) -> Result<Self, Self::Error> {
+        let url = table
+            .remove("url")
+            .ok_or_else(|| <toml::de::Error as serde::de::Error>::missing_field("url"))?;
+        Ok(Self {
+            url: url.try_into()?,
+            settings: toml::Value::Table(table).try_into()?,
+        })
+    }
+}
+
+/// A named account in the configuration file.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AccountConfig {
+    /// The base URL of the service to communicate with.
+    pub service: Option<Url>,
+    /// The username or email of the account.
+    pub identifier: Option<String>,
+    /// The app password to use for authentication.
+    pub password: Option<String>,
+    /// The file used to cache the account session, relative to the data path.
+    pub session_file: Option<PathBuf>,
+    /// The maximum number of posts the account creates in an hour.
+    pub max_posts_per_hour: Option<NonZeroU32>,
+    /// The maximum number of posts the account creates in a day.
+    pub max_posts_per_day: Option<NonZeroU32>,
+}
+
+impl AccountConfig {
+    /// Fill any unset values with the values from `fallback`.
+    pub fn or(self, fallback: AccountConfig) -> AccountConfig {
+        AccountConfig {
+            service: self.service.or(fallback.service),
+            identifier: self.identifier.or(fallback.identifier),
+            password: self.password.or(fallback.password),
+            session_file: self.session_file.or(fallback.session_file),
+            max_posts_per_hour: self.max_posts_per_hour.or(fallback.max_posts_per_hour),
+            max_posts_per_day: self.max_posts_per_day.or(fallback.max_posts_per_day),
+        }
+    }
+}
+
+/// The fully resolved settings for a single account.
+#[derive(Debug, Clone)]
+pub struct AccountOptions {
+    pub service: Url,
+    pub identifier: String,
+    pub password: String,
+    pub session_path: PathBuf,
+    pub max_posts_per_hour: Option<NonZeroU32>,
+    pub max_posts_per_day: Option<NonZeroU32>,
+}
+
+impl AccountOptions {
+    /// Resolve settings for an account, failing if no credentials have been provided.
+    pub fn resolve(name: &str, account: AccountConfig, data_path: &Path) -> Result<Self> {
+        let (Some(identifier), Some(password)) = (account.identifier, account.password) else {
+            bail!("account '{name}' is missing an identifier or password");
+        };
+        let session_file = account.session_file.unwrap_or_else(|| {
+            if name == DEFAULT_ACCOUNT {
+                PathBuf::from("agentconfig.json")
+            } else {
+                PathBuf::from(format!("agentconfig-{name}.json"))
+            }
+        });
+        Ok(Self {
+            service: account
+                .service
+                .unwrap_or_else(|| Url::parse("https://bsky.social").unwrap()),
+            identifier,
+            password,
+            session_path: data_path.join(session_file),
+            max_posts_per_hour: account.max_posts_per_hour,
+            max_posts_per_day: account.max_posts_per_day,
+        })
+    }
+}
+
+/// A source for the date of a feed entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateSource {
+    /// The date the entry was published.
+    Published,
+    /// The date the entry was last updated.
+    Updated,
+    /// The date the entry was first seen in the feed.
+    FirstSeen,
+}
+
+/// How changes to entries that have already been posted are handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateMode {
+    /// Changes are ignored.
+    #[default]
+    Off,
+    /// The existing posts are edited in place.
+    Edit,
+    /// The existing posts are deleted and posted again.
+    Repost,
+}
+
+/// Where the categories of entries are added to posts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum CategoryTags {
+    /// Categories are not added to posts.
+    #[default]
+    Off,
+    /// Categories are added to the tags of the post record, which are not shown in the post text.
+    Tags,
+    /// Categories are added to the end of the post text as hashtags.
+    Hashtags,
+    /// Categories are added both as tags and as hashtags.
+    Both,
+}
+
+impl CategoryTags {
+    pub fn tags(self) -> bool {
+        matches!(self, Self::Tags | Self::Both)
+    }
+
+    pub fn hashtags(self) -> bool {
+        matches!(self, Self::Hashtags | Self::Both)
+    }
+}
+
+/// What is embedded in posts alongside their text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmbedMode {
+    /// A link card for the entry, using the image and description of the page it links to.
+    #[default]
+    Link,
+    /// Up to four images from the entry's media or content, falling back to a link card if it has none.
+    Images,
+    /// The first MP4 video from the entry's media that is within Bluesky's limits, falling back to
+    /// a link card if it has none.
+    Video,
+}
+
+/// How thumbnails are fitted to the aspect ratio of link cards.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThumbnailFit {
+    /// The middle of the image is kept and the edges are cropped off.
+    #[default]
+    Crop,
+    /// The part of the image with the most detail is kept and the rest is cropped off.
+    Entropy,
+    /// The whole image is kept and placed on a blurred copy of itself.
+    PadBlur,
+    /// The whole image is kept and placed on a black background.
+    PadSolid,
+    /// The image is used as it is.
+    Keep,
+}
+
+/// The ratio of the width of an image to its height, written as `{width}:{height}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AspectRatio {
+    pub width: NonZeroU32,
+    pub height: NonZeroU32,
+}
+
+impl AspectRatio {
+    pub const WIDESCREEN: Self = Self {
+        width: NonZeroU32::new(16).unwrap(),
+        height: NonZeroU32::new(9).unwrap(),
+    };
+
+    pub fn value(self) -> f32 {
+        self.width.get() as f32 / self.height.get() as f32
+    }
+}
+
+impl Default for AspectRatio {
+    fn default() -> Self {
+        Self::WIDESCREEN
+    }
+}
+
+impl FromStr for AspectRatio {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (width, height) = value
+            .split_once(':')
+            .context("expected a ratio in the format {width}:{height}")?;
+        Ok(Self {
+            width: width.trim().parse().context("invalid ratio width")?,
+            height: height.trim().parse().context("invalid ratio height")?,
+        })
+    }
+}
+
+impl TryFrom<String> for AspectRatio {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<AspectRatio> for String {
+    fn from(ratio: AspectRatio) -> Self {
+        ratio.to_string()
+    }
+}
+
+impl fmt::Display for AspectRatio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.width, self.height)
+    }
+}
+
+/// A part of a feed entry that filter rules can match against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterField {
+    Title,
+    Summary,
+    Author,
+    Category,
+    LinkHost,
+}
+
+/// A rule that matches feed entries by a keyword or regular expression.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FilterRuleConfig {
+    /// The fields of the entry to match against. Matches against every field if empty.
+    #[serde(default)]
+    pub fields: Vec<FilterField>,
+    /// A word or phrase to match case-insensitively.
+    pub keyword: Option<String>,
+    /// A regular expression to match.
+    pub regex: Option<String>,
+}
+
+/// Settings that can be set globally or per-feed.
+///
+/// Unset values fall through to the next level of configuration.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FeedSettings {
+    pub rerun_interval_seconds: Option<u64>,
+    pub backdate_hours: Option<u16>,
+    pub disable_post_comments: Option<bool>,
+    pub post_languages: Option<Vec<String>>,
+    pub accounts: Option<Vec<String>>,
+    pub post_template: Option<String>,
+    pub date_sources: Option<Vec<DateSource>>,
+    pub update_mode: Option<UpdateMode>,
+    pub retract_missing_entries: Option<bool>,
+    pub retract_after_missing_fetches: Option<u32>,
+    pub retract_window_hours: Option<u16>,
+    pub thread_entries: Option<bool>,
+    pub category_tags: Option<CategoryTags>,
+    pub category_allow: Option<Vec<String>>,
+    pub category_deny: Option<Vec<String>>,
+    pub category_rename: Option<BTreeMap<String, String>>,
+    pub include: Option<Vec<FilterRuleConfig>>,
+    pub exclude: Option<Vec<FilterRuleConfig>>,
+    pub canonicalize_urls: Option<bool>,
+    pub tracking_params: Option<Vec<String>>,
+    pub dedup_entry_ids: Option<bool>,
+    pub max_posts_per_hour: Option<NonZeroU32>,
+    pub max_posts_per_day: Option<NonZeroU32>,
+    pub embed_mode: Option<EmbedMode>,
+    pub max_image_bytes: Option<u64>,
+    pub thumbnail_fit: Option<ThumbnailFit>,
+    pub thumbnail_ratio: Option<AspectRatio>,
+}
+
+impl FeedSettings {
+    /// Fill any unset values with the values from `fallback`.
+    pub fn or(self, fallback: &FeedSettings) -> FeedSettings {
+        FeedSettings {
+            rerun_interval_seconds: self
+                .rerun_interval_seconds
+                .or(fallback.rerun_interval_seconds),
+            backdate_hours: self.backdate_hours.or(fallback.backdate_hours),
+            disable_post_comments: self
+                .disable_post_comments
+                .or(fallback.disable_post_comments),
+            post_languages: self
+                .post_languages
+                .or_else(|| fallback.post_languages.clone()),
+            accounts: self.accounts.or_else(|| fallback.accounts.clone()),
+            post_template: self
+                .post_template
+                .or_else(|| fallback.post_template.clone()),
+            date_sources: self.date_sources.or_else(|| fallback.date_sources.clone()),
+            update_mode: self.update_mode.or(fallback.update_mode),
+            retract_missing_entries: self
+                .retract_missing_entries
+                .or(fallback.retract_missing_entries),
+            retract_after_missing_fetches: self
+                .retract_after_missing_fetches
+                .or(fallback.retract_after_missing_fetches),
+            retract_window_hours: self.retract_window_hours.or(fallback.retract_window_hours),
+            thread_entries: self.thread_entries.or(fallback.thread_entries),
+            category_tags: self.category_tags.or(fallback.category_tags),
+            category_allow: self
+                .category_allow
+                .or_else(|| fallback.category_allow.clone()),
+            category_deny: self
+                .category_deny
+                .or_else(|| fallback.category_deny.clone()),
+            category_rename: self
+                .category_rename
+                .or_else(|| fallback.category_rename.clone()),
+            include: self.include.or_else(|| fallback.include.clone()),
+            exclude: self.exclude.or_else(|| fallback.exclude.clone()),
+            canonicalize_urls: self.canonicalize_urls.or(fallback.canonicalize_urls),
+            tracking_params: self
+                .tracking_params
+                .or_else(|| fallback.tracking_params.clone()),
+            dedup_entry_ids: self.dedup_entry_ids.or(fallback.dedup_entry_ids),
+            max_posts_per_hour: self.max_posts_per_hour.or(fallback.max_posts_per_hour),
+            max_posts_per_day: self.max_posts_per_day.or(fallback.max_posts_per_day),
+            embed_mode: self.embed_mode.or(fallback.embed_mode),
+            max_image_bytes: self.max_image_bytes.or(fallback.max_image_bytes),
+            thumbnail_fit: self.thumbnail_fit.or(fallback.thumbnail_fit),
+            thumbnail_ratio: self.thumbnail_ratio.or(fallback.thumbnail_ratio),
+        }
+    }
+}
+
+/// The fully resolved settings for a single feed.
+#[derive(Debug, Clone)]
+pub struct FeedOptions {
+    pub url: Url,
+    pub rerun_interval_seconds: u64,
+    pub backdate_hours: u16,
+    pub disable_post_comments: bool,
+    pub post_languages: Vec<String>,
+    pub accounts: Vec<String>,
+    pub post_template: String,
+    pub date_sources: Vec<DateSource>,
+    pub update_mode: UpdateMode,
+    pub retract_missing_entries: bool,
+    pub retract_after_missing_fetches: u32,
+    pub retract_window_hours: u16,
+    pub thread_entries: bool,
+    pub category_tags: CategoryTags,
+    /// The only categories that are added to posts, or `None` to allow every category.
+    pub category_allow: Option<Vec<String>>,
+    pub category_deny: Vec<String>,
+    pub category_rename: BTreeMap<String, String>,
+    /// Rules that entries must match at least one of to be posted, if there are any.
+    pub include: Vec<FilterRuleConfig>,
+    /// Rules that skip entries that match any of them.
+    pub exclude: Vec<FilterRuleConfig>,
+    /// Whether links are canonicalized before checking if they have already been posted.
+    pub canonicalize_urls: bool,
+    /// Query parameters removed from links when canonicalizing them. A trailing `*` matches any suffix.
+    pub tracking_params: Vec<String>,
+    /// Whether entries with the same ID as an entry already posted from the feed are skipped.
+    pub dedup_entry_ids: bool,
+    /// The maximum number of entries from the feed each account posts in an hour, if limited.
+    pub max_posts_per_hour: Option<NonZeroU32>,
+    /// The maximum number of entries from the feed each account posts in a day, if limited.
+    pub max_posts_per_day: Option<NonZeroU32>,
+    pub embed_mode: EmbedMode,
+    /// The maximum size of an uploaded image or thumbnail in bytes.
+    pub max_image_bytes: u64,
+    pub thumbnail_fit: ThumbnailFit,
+    /// The aspect ratio thumbnails are fitted to.
+    pub thumbnail_ratio: AspectRatio,
+}
+
+impl FeedOptions {
+    /// Resolve settings for a feed, using built-in defaults for anything left unset.
+    ///
+    /// Fails if any of the settings are invalid.
+    pub fn resolve(url: Url, settings: FeedSettings) -> Result<Self> {
+        let post_languages = settings
+            .post_languages
+            .unwrap_or_else(|| vec![String::from("en")]);
+        parse_languages(&post_languages)
+            .with_context(|| format!("invalid post languages for feed {url}"))?;
+        Ok(Self {
+            url,
+            rerun_interval_seconds: settings.rerun_interval_seconds.unwrap_or(300),
+            backdate_hours: settings.backdate_hours.unwrap_or(3),
+            disable_post_comments: settings.disable_post_comments.unwrap_or(true),
+            post_languages,
+            accounts: dedup(
+                settings
+                    .accounts
+                    .unwrap_or_else(|| vec![String::from(DEFAULT_ACCOUNT)]),
+            ),
+            post_template: settings
+                .post_template
+                .unwrap_or_else(|| String::from(DEFAULT_POST_TEMPLATE)),
+            date_sources: settings.date_sources.unwrap_or_else(|| {
+                vec![
+                    DateSource::Published,
+                    DateSource::Updated,
+                    DateSource::FirstSeen,
+                ]
+            }),
+            update_mode: settings.update_mode.unwrap_or_default(),
+            retract_missing_entries: settings.retract_missing_entries.unwrap_or(false),
+            retract_after_missing_fetches: settings.retract_after_missing_fetches.unwrap_or(3),
+            retract_window_hours: settings.retract_window_hours.unwrap_or(24),
+            thread_entries: settings.thread_entries.unwrap_or(false),
+            category_tags: settings.category_tags.unwrap_or_default(),
+            category_allow: settings.category_allow,
+            category_deny: settings.category_deny.unwrap_or_default(),
+            category_rename: settings.category_rename.unwrap_or_default(),
+            include: settings.include.unwrap_or_default(),
+            exclude: settings.exclude.unwrap_or_default(),
+            canonicalize_urls: settings.canonicalize_urls.unwrap_or(true),
+            tracking_params: settings.tracking_params.unwrap_or_else(|| {
+                DEFAULT_TRACKING_PARAMS
+                    .iter()
+                    .map(|param| param.to_string())
+                    .collect()
+            }),
+            dedup_entry_ids: settings.dedup_entry_ids.unwrap_or(true),
+            max_posts_per_hour: settings.max_posts_per_hour,
+            max_posts_per_day: settings.max_posts_per_day,
+            embed_mode: settings.embed_mode.unwrap_or_default(),
+            max_image_bytes: settings
+                .max_image_bytes
+                .unwrap_or(ImageOptions::default().max_bytes),
+            thumbnail_fit: settings.thumbnail_fit.unwrap_or_default(),
+            thumbnail_ratio: settings.thumbnail_ratio.unwrap_or_default(),
+        })
+    }
+}
+
+/// Remove repeated values from a list, keeping the first occurrence of each.
+fn dedup(mut values: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    values.retain(|value| seen.insert(value.clone()));
+    values
+}
+
+impl ConfigFile {
+    /// Load the configuration file at the given path.
+    ///
+    /// When `required` is false a missing file is treated as an empty configuration.
+    pub fn load(path: &Path, required: bool) -> Result<Self> {
+        if !required && !fs::exists(path)? {
+            debug!("No configuration file found at {path:?}, using empty configuration");
+            return Ok(Self::default());
+        }
+        debug!("Loading configuration file from {path:?}");
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read configuration file at {path:?}"))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("failed to parse configuration file at {path:?}"))
+    }
+}
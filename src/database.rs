@@ -1,9 +1,109 @@
+use crate::bsky::PostRef;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use log::debug;
-use sqlx::{SqlitePool, migrate, query};
+use sqlx::{SqlitePool, migrate, query, query_as};
 
 type DatabasePool = SqlitePool;
 
+/// The keys used to find an entry that has already been posted.
+#[derive(Debug, Clone, Copy)]
+pub struct PostedKeys<'a> {
+    pub url: &'a str,
+    /// The canonicalized form of the URL.
+    pub url_key: &'a str,
+    /// The URL of the feed and the ID of the entry, if entries should also be matched by ID.
+    pub entry: Option<(&'a str, &'a str)>,
+}
+
+/// A URL that has been posted, along with the feed entry it came from if known.
+#[derive(Debug, Default, Clone)]
+pub struct PostedUrl {
+    pub url: String,
+    /// The canonicalized form of the URL.
+    pub url_key: Option<String>,
+    pub feed_url: Option<String>,
+    pub entry_id: Option<String>,
+    pub title: Option<String>,
+    pub posted_at: Option<DateTime<Utc>>,
+    /// A hash of the entry content when it was last posted or updated.
+    pub content_hash: Option<String>,
+    /// The updated date of the entry when it was last posted or updated.
+    pub entry_updated_at: Option<DateTime<Utc>>,
+    /// When the posts for the entry were deleted because it was removed from its feed.
+    pub retracted_at: Option<DateTime<Utc>>,
+}
+
+/// A recently posted entry that may be retracted if it is removed from its feed.
+#[derive(Debug, Clone)]
+pub struct RetractionCandidate {
+    pub url: String,
+    pub url_key: Option<String>,
+    pub entry_id: Option<String>,
+    pub posted_at: DateTime<Utc>,
+    /// The number of consecutive fetches the entry has been missing from.
+    pub missing_fetches: i64,
+}
+
+/// A Bluesky post record that was created for a posted URL.
+#[derive(Debug, Clone)]
+pub struct PostRecord {
+    pub uri: String,
+    pub cid: String,
+    pub url: String,
+    pub account: String,
+    /// The URI of the post this is a reply to the thread of, if it is a reply.
+    pub root_uri: Option<String>,
+}
+
+impl PostRecord {
+    /// Build the records for a post created for a URL, followed by its replies.
+    pub fn from_thread(url: &str, account: &str, thread: Vec<PostRef>) -> Vec<Self> {
+        let root_uri = thread.first().map(|root| root.uri.clone());
+        thread
+            .into_iter()
+            .enumerate()
+            .map(|(index, post)| Self {
+                uri: post.uri,
+                cid: post.cid,
+                url: url.to_string(),
+                account: account.to_string(),
+                root_uri: root_uri.clone().filter(|_| index > 0),
+            })
+            .collect()
+    }
+}
+
+/// A post in the outbox that is waiting to be retried.
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub url: String,
+    pub account: String,
+    /// The feed the post was created for, if known.
+    pub feed_url: Option<String>,
+    pub post_data: String,
+    pub attempts: i64,
+}
+
+/// A post to add to the outbox.
+#[derive(Debug, Clone, Copy)]
+pub struct NewOutboxEntry<'a> {
+    pub url: &'a str,
+    pub account: &'a str,
+    pub feed_url: Option<&'a str>,
+    /// The date of the entry the post was created for, used to create queued posts in order.
+    pub entry_date: Option<DateTime<Utc>>,
+    pub post_data: &'a str,
+}
+
+/// HTTP cache validators returned by a feed, used to make conditional requests.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FeedValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct Database {
     pool: DatabasePool,
@@ -22,6 +122,27 @@ impl Database {
         Ok(())
     }
 
+    pub async fn remove_old_seen_entries(&self) -> Result<()> {
+        debug!("Removing old seen_entries entries");
+        query!("DELETE FROM seen_entries WHERE ROWID IN (SELECT ROWID FROM seen_entries ORDER BY ROWID DESC LIMIT -1 OFFSET 25000)").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn remove_old_skipped_entries(&self) -> Result<()> {
+        debug!("Removing old skipped_entries entries");
+        query!("DELETE FROM skipped_entries WHERE ROWID IN (SELECT ROWID FROM skipped_entries ORDER BY ROWID DESC LIMIT -1 OFFSET 25000)").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn remove_old_post_log_entries(&self, before: DateTime<Utc>) -> Result<()> {
+        debug!("Removing old post_log entries");
+        let before = before.timestamp();
+        query!("DELETE FROM post_log WHERE posted_at < ?", before)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     pub async fn get_all_post_urls(&self) -> Result<Option<Vec<String>>> {
         debug!("Fetching all urls in the posted_urls table");
         let q = query!(r#"SELECT url FROM posted_urls"#)
@@ -33,22 +154,261 @@ impl Database {
         Ok(q)
     }
 
-    pub async fn has_posted_url(&self, url: &str) -> Result<bool> {
-        debug!("Checking if {url} exists in posted_urls table");
-        Ok(query!("SELECT url FROM posted_urls WHERE url = ?", url)
-            .fetch_optional(&self.pool)
-            .await?
-            .is_some())
+    /// Find the URL an entry was stored under if it has already been posted, preferring an exact match.
+    pub async fn find_posted_url(&self, keys: PostedKeys<'_>) -> Result<Option<String>> {
+        debug!("Checking if {} exists in posted_urls table", keys.url);
+        let (feed_url, entry_id) = keys.entry.unzip();
+        Ok(query!(
+            r#"SELECT url as "url!" FROM posted_urls
+            WHERE url = ? OR url_key = ? OR (feed_url = ? AND entry_id = ?)
+            ORDER BY url = ? DESC LIMIT 1"#,
+            keys.url,
+            keys.url_key,
+            feed_url,
+            entry_id,
+            keys.url
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| row.url))
+    }
+
+    /// Get the URLs that were stored without a canonicalized key, along with the feed they came from if known.
+    pub async fn get_urls_without_key(&self) -> Result<Vec<(String, Option<String>)>> {
+        debug!("Fetching urls without a url_key in the posted_urls table");
+        Ok(
+            query!(r#"SELECT url as "url!", feed_url FROM posted_urls WHERE url_key IS NULL"#)
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|row| (row.url, row.feed_url))
+                .collect(),
+        )
+    }
+
+    pub async fn set_url_key(&self, url: &str, url_key: &str) -> Result<()> {
+        debug!("Setting url_key of {url} to {url_key}");
+        query!(
+            "UPDATE posted_urls SET url_key = ? WHERE url = ?",
+            url_key,
+            url
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
     }
 
-    pub async fn insert_posted_url(&self, url: &str) -> Result<()> {
+    pub async fn insert_posted_url(&self, url: &str, url_key: &str) -> Result<()> {
         debug!("Storing {url} in posted_urls");
-        query!("INSERT INTO posted_urls (url) VALUES (?)", url)
-            .execute(&self.pool)
+        query!(
+            "INSERT INTO posted_urls (url, url_key) VALUES (?, ?)",
+            url,
+            url_key
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Store a posted URL along with the feed entry it came from.
+    pub async fn insert_posted_entry(&self, posted: &PostedUrl) -> Result<()> {
+        debug!("Storing {} in posted_urls", posted.url);
+        let posted_at = posted.posted_at.map(|date| date.timestamp());
+        let entry_updated_at = posted.entry_updated_at.map(|date| date.timestamp());
+        query!(
+            "INSERT INTO posted_urls (url, url_key, feed_url, entry_id, title, posted_at, content_hash, entry_updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            posted.url,
+            posted.url_key,
+            posted.feed_url,
+            posted.entry_id,
+            posted.title,
+            posted_at,
+            posted.content_hash,
+            entry_updated_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_posted_url(&self, url: &str) -> Result<Option<PostedUrl>> {
+        debug!("Fetching {url} from posted_urls");
+        Ok(query!(
+            r#"SELECT url as "url!", url_key, feed_url, entry_id, title, posted_at, content_hash, entry_updated_at,
+            retracted_at FROM posted_urls WHERE url = ?"#,
+            url
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| PostedUrl {
+            url: row.url,
+            url_key: row.url_key,
+            feed_url: row.feed_url,
+            entry_id: row.entry_id,
+            title: row.title,
+            posted_at: row
+                .posted_at
+                .and_then(|posted_at| DateTime::from_timestamp(posted_at, 0)),
+            content_hash: row.content_hash,
+            entry_updated_at: row
+                .entry_updated_at
+                .and_then(|updated_at| DateTime::from_timestamp(updated_at, 0)),
+            retracted_at: row
+                .retracted_at
+                .and_then(|retracted_at| DateTime::from_timestamp(retracted_at, 0)),
+        }))
+    }
+
+    /// Get the entries from a feed that were posted after `since` and have not been retracted.
+    pub async fn get_retraction_candidates(
+        &self,
+        feed_url: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<RetractionCandidate>> {
+        debug!("Fetching entries from {feed_url} posted since {since}");
+        let since = since.timestamp();
+        Ok(query!(
+            r#"SELECT url as "url!", url_key, entry_id, posted_at as "posted_at!", missing_fetches FROM posted_urls
+            WHERE feed_url = ? AND posted_at >= ? AND retracted_at IS NULL"#,
+            feed_url,
+            since
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| RetractionCandidate {
+            url: row.url,
+            url_key: row.url_key,
+            entry_id: row.entry_id,
+            posted_at: DateTime::from_timestamp(row.posted_at, 0).unwrap_or_default(),
+            missing_fetches: row.missing_fetches,
+        })
+        .collect())
+    }
+
+    pub async fn set_missing_fetches(&self, url: &str, missing_fetches: i64) -> Result<()> {
+        debug!("Setting missing fetches of {url} to {missing_fetches}");
+        query!(
+            "UPDATE posted_urls SET missing_fetches = ? WHERE url = ?",
+            missing_fetches,
+            url
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Mark a posted URL as retracted, removing the records of its deleted posts.
+    pub async fn mark_retracted(&self, url: &str, retracted_at: DateTime<Utc>) -> Result<()> {
+        debug!("Marking {url} as retracted");
+        let retracted_at = retracted_at.timestamp();
+        let mut transaction = self.pool.begin().await?;
+        query!("DELETE FROM post_records WHERE url = ?", url)
+            .execute(&mut *transaction)
             .await?;
+        query!(
+            "UPDATE posted_urls SET retracted_at = ? WHERE url = ?",
+            retracted_at,
+            url
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Store the latest version of an entry that has already been posted.
+    pub async fn update_posted_entry_version(
+        &self,
+        url: &str,
+        title: Option<&str>,
+        content_hash: &str,
+        entry_updated_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        debug!("Storing latest version of {url} in posted_urls");
+        let entry_updated_at = entry_updated_at.map(|date| date.timestamp());
+        query!(
+            "UPDATE posted_urls SET title = ?, content_hash = ?, entry_updated_at = ? WHERE url = ?",
+            title,
+            content_hash,
+            entry_updated_at,
+            url
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Store a post record created for a URL that is already in posted_urls.
+    pub async fn insert_post_record(&self, record: &PostRecord) -> Result<()> {
+        debug!("Storing post record {} for {}", record.uri, record.url);
+        query!(
+            "INSERT INTO post_records (uri, cid, url, account, root_uri) VALUES (?, ?, ?, ?, ?)",
+            record.uri,
+            record.cid,
+            record.url,
+            record.account,
+            record.root_uri
+        )
+        .execute(&self.pool)
+        .await?;
         Ok(())
     }
 
+    /// Replace the stored records of a post and its replies with the records of the thread that replaced it.
+    pub async fn replace_thread_records(
+        &self,
+        root_uri: &str,
+        records: &[PostRecord],
+    ) -> Result<()> {
+        debug!("Replacing post record {root_uri} and its replies");
+        let mut transaction = self.pool.begin().await?;
+        query!(
+            "DELETE FROM post_records WHERE uri = ? OR root_uri = ?",
+            root_uri,
+            root_uri
+        )
+        .execute(&mut *transaction)
+        .await?;
+        for record in records {
+            query!(
+                "INSERT INTO post_records (uri, cid, url, account, root_uri) VALUES (?, ?, ?, ?, ?)",
+                record.uri,
+                record.cid,
+                record.url,
+                record.account,
+                record.root_uri
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_post_records_for_url(&self, url: &str) -> Result<Vec<PostRecord>> {
+        debug!("Fetching post records for {url}");
+        Ok(query_as!(
+            PostRecord,
+            "SELECT uri, cid, url, account, root_uri FROM post_records WHERE url = ? ORDER BY account, ROWID",
+            url
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    pub async fn get_post_record(&self, uri: &str) -> Result<Option<PostRecord>> {
+        debug!("Fetching post record {uri}");
+        Ok(query_as!(
+            PostRecord,
+            "SELECT uri, cid, url, account, root_uri FROM post_records WHERE uri = ?",
+            uri
+        )
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
     pub async fn delete_posted_url(&self, url: &str) -> Result<()> {
         debug!("Removing {url} from posted_urls");
         query!("DELETE FROM posted_urls WHERE url = ?", url)
@@ -56,4 +416,347 @@ impl Database {
             .await?;
         Ok(())
     }
+
+    pub async fn get_feed_validators(&self, feed_url: &str) -> Result<Option<FeedValidators>> {
+        debug!("Fetching cache validators for {feed_url}");
+        Ok(query_as!(
+            FeedValidators,
+            "SELECT etag, last_modified FROM feed_validators WHERE feed_url = ?",
+            feed_url
+        )
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    pub async fn set_feed_validators(
+        &self,
+        feed_url: &str,
+        validators: &FeedValidators,
+    ) -> Result<()> {
+        debug!("Storing cache validators for {feed_url}");
+        query!(
+            "INSERT INTO feed_validators (feed_url, etag, last_modified) VALUES (?, ?, ?)
+            ON CONFLICT (feed_url) DO UPDATE SET etag = excluded.etag, last_modified = excluded.last_modified",
+            feed_url,
+            validators.etag,
+            validators.last_modified
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn has_seen_feed(&self, feed_url: &str) -> Result<bool> {
+        debug!("Checking if any entries from {feed_url} exist in seen_entries table");
+        Ok(query!(
+            "SELECT entry_id FROM seen_entries WHERE feed_url = ? LIMIT 1",
+            feed_url
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .is_some())
+    }
+
+    /// Check whether an entry was skipped by filter rules with the given fingerprint.
+    pub async fn is_entry_skipped(
+        &self,
+        feed_url: &str,
+        entry_id: &str,
+        rules_fingerprint: &str,
+    ) -> Result<bool> {
+        debug!("Checking if {entry_id} from {feed_url} exists in skipped_entries table");
+        Ok(query!(
+            "SELECT entry_id FROM skipped_entries WHERE feed_url = ? AND entry_id = ? AND rules_fingerprint = ?",
+            feed_url,
+            entry_id,
+            rules_fingerprint
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .is_some())
+    }
+
+    pub async fn insert_skipped_entry(
+        &self,
+        feed_url: &str,
+        entry_id: &str,
+        link: &str,
+        rule: &str,
+        rules_fingerprint: &str,
+        skipped_at: DateTime<Utc>,
+    ) -> Result<()> {
+        debug!("Storing {entry_id} from {feed_url} in skipped_entries");
+        let skipped_at = skipped_at.timestamp();
+        query!(
+            "INSERT INTO skipped_entries (feed_url, entry_id, link, rule, rules_fingerprint, skipped_at) VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (feed_url, entry_id) DO UPDATE SET link = excluded.link, rule = excluded.rule,
+            rules_fingerprint = excluded.rules_fingerprint, skipped_at = excluded.skipped_at",
+            feed_url,
+            entry_id,
+            link,
+            rule,
+            rules_fingerprint,
+            skipped_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get the date an entry was first seen, if it has been seen before.
+    pub async fn get_first_seen(
+        &self,
+        feed_url: &str,
+        entry_id: &str,
+    ) -> Result<Option<DateTime<Utc>>> {
+        debug!("Fetching first seen date of {entry_id} from {feed_url}");
+        Ok(query!(
+            "SELECT first_seen_at FROM seen_entries WHERE feed_url = ? AND entry_id = ?",
+            feed_url,
+            entry_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .and_then(|row| DateTime::from_timestamp(row.first_seen_at, 0)))
+    }
+
+    /// Get the date an entry was first seen, storing `seen_at` if it has not been seen before.
+    pub async fn get_or_insert_first_seen(
+        &self,
+        feed_url: &str,
+        entry_id: &str,
+        seen_at: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        debug!("Fetching first seen date of {entry_id} from {feed_url}");
+        let seen_at = seen_at.timestamp();
+        query!(
+            "INSERT OR IGNORE INTO seen_entries (feed_url, entry_id, first_seen_at) VALUES (?, ?, ?)",
+            feed_url,
+            entry_id,
+            seen_at
+        )
+        .execute(&self.pool)
+        .await?;
+        let first_seen_at = query!(
+            "SELECT first_seen_at FROM seen_entries WHERE feed_url = ? AND entry_id = ?",
+            feed_url,
+            entry_id
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .first_seen_at;
+        Ok(DateTime::from_timestamp(first_seen_at, 0).unwrap_or_default())
+    }
+
+    pub async fn insert_outbox_entry(
+        &self,
+        entry: &NewOutboxEntry<'_>,
+        next_attempt_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()> {
+        debug!("Storing {} for {} in outbox", entry.url, entry.account);
+        let entry_date = entry.entry_date.map(|date| date.timestamp());
+        let next_attempt_at = next_attempt_at.timestamp();
+        query!(
+            "INSERT INTO outbox (url, account, feed_url, entry_date, post_data, attempts, next_attempt_at, last_error)
+            VALUES (?, ?, ?, ?, ?, 1, ?, ?)
+            ON CONFLICT (url, account) DO UPDATE SET feed_url = excluded.feed_url, entry_date = excluded.entry_date,
+            post_data = excluded.post_data, status = 'pending', attempts = 1, next_attempt_at = excluded.next_attempt_at,
+            last_error = excluded.last_error",
+            entry.url,
+            entry.account,
+            entry.feed_url,
+            entry_date,
+            entry.post_data,
+            next_attempt_at,
+            error
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Store a post in the outbox that has not been attempted yet, to be posted at `next_attempt_at`.
+    pub async fn insert_deferred_outbox_entry(
+        &self,
+        entry: &NewOutboxEntry<'_>,
+        next_attempt_at: DateTime<Utc>,
+    ) -> Result<()> {
+        debug!(
+            "Storing {} for {} in outbox until {next_attempt_at}",
+            entry.url, entry.account
+        );
+        let entry_date = entry.entry_date.map(|date| date.timestamp());
+        let next_attempt_at = next_attempt_at.timestamp();
+        query!(
+            "INSERT INTO outbox (url, account, feed_url, entry_date, post_data, attempts, next_attempt_at)
+            VALUES (?, ?, ?, ?, ?, 0, ?)
+            ON CONFLICT (url, account) DO UPDATE SET feed_url = excluded.feed_url, entry_date = excluded.entry_date,
+            post_data = excluded.post_data, status = 'pending', attempts = 0, next_attempt_at = excluded.next_attempt_at,
+            last_error = NULL",
+            entry.url,
+            entry.account,
+            entry.feed_url,
+            entry_date,
+            entry.post_data,
+            next_attempt_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Check whether an account has posts in the outbox that have not been created yet.
+    pub async fn has_pending_outbox_entries(&self, account: &str) -> Result<bool> {
+        debug!("Checking if {account} has pending entries in outbox");
+        Ok(query!(
+            "SELECT id FROM outbox WHERE account = ? AND status = 'pending' LIMIT 1",
+            account
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .is_some())
+    }
+
+    /// Get the pending outbox entries that are due, oldest first.
+    ///
+    /// Entries are ordered by the date of their feed entry, so that posts queued from several
+    /// feeds are created in the same order as they would be from a single feed.
+    pub async fn get_due_outbox_entries(&self, now: DateTime<Utc>) -> Result<Vec<OutboxEntry>> {
+        debug!("Fetching due entries from outbox");
+        let now = now.timestamp();
+        Ok(query_as!(
+            OutboxEntry,
+            "SELECT id, url, account, feed_url, post_data, attempts FROM outbox
+            WHERE status = 'pending' AND next_attempt_at <= ?
+            ORDER BY entry_date IS NULL, entry_date, next_attempt_at, url, id",
+            now
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    pub async fn reschedule_outbox_entry(
+        &self,
+        id: i64,
+        attempts: i64,
+        next_attempt_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<()> {
+        debug!("Rescheduling outbox entry {id} for {next_attempt_at}");
+        let next_attempt_at = next_attempt_at.timestamp();
+        query!(
+            "UPDATE outbox SET attempts = ?, next_attempt_at = ?, last_error = ? WHERE id = ?",
+            attempts,
+            next_attempt_at,
+            error,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Move an outbox entry to a later time without counting it as an attempt.
+    pub async fn defer_outbox_entry(&self, id: i64, next_attempt_at: DateTime<Utc>) -> Result<()> {
+        debug!("Deferring outbox entry {id} until {next_attempt_at}");
+        let next_attempt_at = next_attempt_at.timestamp();
+        query!(
+            "UPDATE outbox SET next_attempt_at = ? WHERE id = ?",
+            next_attempt_at,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn mark_outbox_entry_dead(&self, id: i64, attempts: i64, error: &str) -> Result<()> {
+        debug!("Marking outbox entry {id} as dead");
+        query!(
+            "UPDATE outbox SET status = 'dead', attempts = ?, last_error = ? WHERE id = ?",
+            attempts,
+            error,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_outbox_entries_for_url(&self, url: &str) -> Result<()> {
+        debug!("Removing outbox entries for {url}");
+        query!("DELETE FROM outbox WHERE url = ?", url)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_outbox_entry(&self, id: i64) -> Result<()> {
+        debug!("Removing outbox entry {id}");
+        query!("DELETE FROM outbox WHERE id = ?", id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Record that a post was created by an account, used to enforce rate limits.
+    pub async fn insert_post_log_entry(
+        &self,
+        account: &str,
+        feed_url: Option<&str>,
+        posted_at: DateTime<Utc>,
+    ) -> Result<()> {
+        debug!("Storing post by {account} in post_log");
+        let posted_at = posted_at.timestamp();
+        query!(
+            "INSERT INTO post_log (account, feed_url, posted_at) VALUES (?, ?, ?)",
+            account,
+            feed_url,
+            posted_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Get the times of the posts created by an account since the given date, oldest first.
+    pub async fn get_account_post_times(
+        &self,
+        account: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>> {
+        let since = since.timestamp();
+        Ok(query!(
+            "SELECT posted_at FROM post_log WHERE account = ? AND posted_at >= ? ORDER BY posted_at",
+            account,
+            since
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| DateTime::from_timestamp(row.posted_at, 0).unwrap_or_default())
+        .collect())
+    }
+
+    /// Get the times of the posts created for a feed by an account since the given date, oldest first.
+    pub async fn get_feed_post_times(
+        &self,
+        feed_url: &str,
+        account: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>> {
+        let since = since.timestamp();
+        Ok(query!(
+            "SELECT posted_at FROM post_log WHERE feed_url = ? AND account = ? AND posted_at >= ? ORDER BY posted_at",
+            feed_url,
+            account,
+            since
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| DateTime::from_timestamp(row.posted_at, 0).unwrap_or_default())
+        .collect())
+    }
 }
@@ -0,0 +1,179 @@
+use crate::compose::html_to_text;
+use crate::config::{FilterField, FilterRuleConfig};
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use feed_rs::model::Entry;
+use regex::Regex;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// The fields matched by rules that do not list any.
+const ALL_FIELDS: &[FilterField] = &[
+    FilterField::Title,
+    FilterField::Summary,
+    FilterField::Author,
+    FilterField::Category,
+    FilterField::LinkHost,
+];
+
+/// A compiled filter rule.
+#[derive(Debug)]
+struct FilterRule {
+    fields: Vec<FilterField>,
+    regex: Regex,
+    /// A description of the rule, used to record why an entry was skipped.
+    description: String,
+}
+
+impl FilterRule {
+    fn new(config: &FilterRuleConfig) -> Result<Self> {
+        let (pattern, description) = match (&config.keyword, &config.regex) {
+            (Some(keyword), None) => (
+                format!(r"(?i)(?:^|\W){}(?:$|\W)", regex::escape(keyword)),
+                format!("keyword '{keyword}'"),
+            ),
+            (None, Some(regex)) => (regex.clone(), format!("regex '{regex}'")),
+            _ => bail!("filter rules must have either a keyword or a regex, but not both"),
+        };
+        let regex = Regex::new(&pattern)
+            .with_context(|| format!("invalid regular expression in filter rule {description}"))?;
+
+        if config.fields.is_empty() {
+            return Ok(Self {
+                fields: ALL_FIELDS.to_vec(),
+                regex,
+                description,
+            });
+        }
+        let field_names = config
+            .fields
+            .iter()
+            .filter_map(|field| field.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok(Self {
+            fields: config.fields.clone(),
+            regex,
+            description: format!("{description} on {field_names}"),
+        })
+    }
+
+    fn matches(&self, values: &HashMap<FilterField, Vec<String>>) -> bool {
+        self.fields.iter().any(|field| {
+            values
+                .get(field)
+                .is_some_and(|values| values.iter().any(|value| self.regex.is_match(value)))
+        })
+    }
+}
+
+/// Decides whether feed entries should be posted using include, exclude and blocklist rules.
+#[derive(Debug)]
+pub struct EntryFilter {
+    blocklist: Vec<FilterRule>,
+    include: Vec<FilterRule>,
+    exclude: Vec<FilterRule>,
+    fingerprint: String,
+}
+
+impl EntryFilter {
+    pub fn new(
+        blocklist: &[FilterRuleConfig],
+        include: &[FilterRuleConfig],
+        exclude: &[FilterRuleConfig],
+    ) -> Result<Self> {
+        let compile = |rules: &[FilterRuleConfig]| {
+            rules
+                .iter()
+                .map(FilterRule::new)
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            blocklist: compile(blocklist)?,
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+            fingerprint: format!(
+                "{:x}",
+                Sha256::digest(format!("{blocklist:?}{include:?}{exclude:?}"))
+            ),
+        })
+    }
+
+    /// A hash of the rules, used to tell whether an entry was skipped by the current rules.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocklist.is_empty() && self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Check whether an entry should be skipped, returning a description of the rule that skipped it.
+    ///
+    /// The blocklist is checked first, then the exclude rules, and then the include rules.
+    pub fn check(&self, entry: &Entry, link: &str) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let values = entry_values(entry, link);
+        if let Some(rule) = self.blocklist.iter().find(|rule| rule.matches(&values)) {
+            return Some(format!("blocklist {}", rule.description));
+        }
+        if let Some(rule) = self.exclude.iter().find(|rule| rule.matches(&values)) {
+            return Some(format!("exclude {}", rule.description));
+        }
+        if !self.include.is_empty() && !self.include.iter().any(|rule| rule.matches(&values)) {
+            return Some(String::from("no include rule"));
+        }
+        None
+    }
+}
+
+/// Collect the values of every field of an entry that rules can match against.
+fn entry_values(entry: &Entry, link: &str) -> HashMap<FilterField, Vec<String>> {
+    HashMap::from([
+        (
+            FilterField::Title,
+            entry
+                .title
+                .iter()
+                .map(|title| title.content.trim().to_string())
+                .collect(),
+        ),
+        (
+            FilterField::Summary,
+            entry
+                .summary
+                .iter()
+                .map(|summary| html_to_text(&summary.content))
+                .collect(),
+        ),
+        (
+            FilterField::Author,
+            entry
+                .authors
+                .iter()
+                .map(|person| person.name.clone())
+                .collect(),
+        ),
+        (
+            FilterField::Category,
+            entry
+                .categories
+                .iter()
+                .flat_map(|category| category.label.iter().chain([&category.term]))
+                .cloned()
+                .collect(),
+        ),
+        (
+            FilterField::LinkHost,
+            Url::parse(link)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .into_iter()
+                .collect(),
+        ),
+    ])
+}
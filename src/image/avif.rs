@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use avif_parse::AV1Metadata;
+use image::{DynamicImage, ImageFormat, Limits};
+
+/// Decode an AVIF image.
+///
+/// The `image` crate decodes the whole image before checking it against its limits, so the size
+/// of the image is read from its AV1 sequence headers and checked against the default limits
+/// before anything is decoded.
+pub fn decode_avif(image_bytes: &[u8]) -> Result<DynamicImage> {
+    let avif =
+        avif_parse::read_avif(&mut &image_bytes[..]).context("failed to read AVIF container")?;
+    let mut limits = Limits::default();
+    reserve_frame(&mut limits, &avif.primary_item_metadata()?)?;
+    if let Some(metadata) = avif.alpha_item_metadata()? {
+        reserve_frame(&mut limits, &metadata)?;
+    }
+
+    let image = image::load_from_memory_with_format(image_bytes, ImageFormat::Avif)?;
+    if !avif.premultiplied_alpha {
+        return Ok(image);
+    }
+    // The decoder leaves the color premultiplied by the alpha, which is undone here.
+    let mut image = image.into_rgba8();
+    for pixel in image.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        if a > 0 {
+            let unpremultiply = |value: u8| (u32::from(value) * 255 / u32::from(a)).min(255) as u8;
+            pixel.0 = [unpremultiply(r), unpremultiply(g), unpremultiply(b), a];
+        }
+    }
+    Ok(image.into())
+}
+
+/// Check that a frame of the size declared by its sequence header fits within the limits, and
+/// reserve the memory used to decode and convert it.
+fn reserve_frame(limits: &mut Limits, metadata: &AV1Metadata) -> Result<()> {
+    let (width, height) = (
+        metadata.max_frame_width.get(),
+        metadata.max_frame_height.get(),
+    );
+    limits.check_dimensions(width, height)?;
+    // Three planes of up to 16 bits each, and the RGBA image they are converted to.
+    limits.reserve(u64::from(width) * u64::from(height) * (3 * 2 + 4))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_avif, reserve_frame};
+    use image::{Limits, RgbaImage};
+
+    /// The colors of the quadrants of the color fixtures, from the top left to the bottom right.
+    const QUADRANTS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    /// A pixel in the middle of each quadrant of the 16x16 fixtures, away from chroma edges.
+    const QUADRANT_PIXELS: [(u32, u32); 4] = [(3, 3), (12, 3), (3, 12), (12, 12)];
+
+    fn read(name: &str) -> Vec<u8> {
+        std::fs::read(format!(
+            "{}/tests/fixtures/avif/{name}",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap()
+    }
+
+    fn decode(name: &str) -> RgbaImage {
+        let image = decode_avif(&read(name)).unwrap();
+        assert_eq!((image.width(), image.height()), (16, 16));
+        image.to_rgba8()
+    }
+
+    fn assert_pixel(image: &RgbaImage, (x, y): (u32, u32), expected: [u8; 4]) {
+        let actual = image.get_pixel(x, y).0;
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(actual, expected)| actual.abs_diff(expected) <= 3),
+            "pixel at ({x}, {y}) is {actual:?}, expected {expected:?}"
+        );
+    }
+
+    #[test]
+    fn decodes_8_bit_420() {
+        let image = decode("8bit-420.avif");
+        for (pixel, [r, g, b]) in QUADRANT_PIXELS.into_iter().zip(QUADRANTS) {
+            assert_pixel(&image, pixel, [r, g, b, 255]);
+        }
+    }
+
+    #[test]
+    fn decodes_10_bit_limited_range() {
+        let image = decode("10bit-420.avif");
+        for (pixel, [r, g, b]) in QUADRANT_PIXELS.into_iter().zip(QUADRANTS) {
+            assert_pixel(&image, pixel, [r, g, b, 255]);
+        }
+    }
+
+    #[test]
+    fn decodes_premultiplied_alpha() {
+        let image = decode("alpha-premultiplied.avif");
+        assert_pixel(&image, (3, 8), [255, 0, 0, 255]);
+        assert_pixel(&image, (12, 8), [200, 100, 50, 128]);
+    }
+
+    #[test]
+    fn decodes_monochrome() {
+        let image = decode("monochrome.avif");
+        for (pixel, value) in QUADRANT_PIXELS.into_iter().zip([0, 85, 170, 255]) {
+            assert_pixel(&image, pixel, [value, value, value, 255]);
+        }
+    }
+
+    #[test]
+    fn rejects_frames_over_the_size_limit() {
+        let avif = avif_parse::read_avif(&mut &read("8bit-420.avif")[..]).unwrap();
+        let metadata = avif.primary_item_metadata().unwrap();
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(16);
+        assert!(reserve_frame(&mut limits, &metadata).is_ok());
+        limits.max_image_width = Some(15);
+        assert!(reserve_frame(&mut limits, &metadata).is_err());
+        let mut limits = Limits::default();
+        limits.max_alloc = Some(16 * 16 * 10 - 1);
+        assert!(reserve_frame(&mut limits, &metadata).is_err());
+    }
+}
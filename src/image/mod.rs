@@ -0,0 +1,293 @@
+mod avif;
+mod svg;
+
+use std::io::Cursor;
+
+use crate::config::{AspectRatio, ThumbnailFit};
+use avif::decode_avif;
+use image::{
+    DynamicImage, GenericImageView, ImageFormat, ImageReader, Rgb, RgbImage, Rgba, RgbaImage,
+    codecs::{jpeg::JpegEncoder, png::PngDecoder, webp::WebPDecoder},
+    imageops::{self, FilterType},
+};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use svg::{is_svg, rasterize_svg};
+
+/// The JPEG qualities tried in order when encoding an image, until it fits within the size limit.
+const JPEG_QUALITIES: [u8; 6] = [90, 80, 70, 60, 50, 40];
+
+/// The number of crop positions compared when looking for the part of an image with the most detail.
+const ENTROPY_CROP_STEPS: u32 = 16;
+
+/// The size images are shrunk to before measuring their detail or blurring them, to keep it fast.
+const SAMPLE_SIZE: u32 = 256;
+
+/// How images are converted before they are uploaded.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageOptions {
+    /// The maximum size of an uploaded image in bytes.
+    pub max_bytes: u64,
+    /// The maximum width and height of an uploaded image, which larger images are downscaled to.
+    pub max_dimension: u32,
+    /// How thumbnails are fitted to `thumbnail_ratio`.
+    pub thumbnail_fit: ThumbnailFit,
+    pub thumbnail_ratio: AspectRatio,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1_000_000,
+            max_dimension: 2000,
+            thumbnail_fit: ThumbnailFit::default(),
+            thumbnail_ratio: AspectRatio::default(),
+        }
+    }
+}
+
+/// An image that has been converted so it is ready to be uploaded.
+#[derive(Debug)]
+pub struct PreparedImage {
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Read the width and height of an encoded image without decoding all of it.
+pub fn image_dimensions(image_bytes: &[u8]) -> anyhow::Result<(u32, u32)> {
+    Ok(ImageReader::new(Cursor::new(image_bytes))
+        .with_guessed_format()?
+        .into_dimensions()?)
+}
+
+/// Check whether an encoded image can be uploaded as it is.
+///
+/// This is only the case for static JPEG, PNG and WebP images, as other formats and animations
+/// are not shown properly by Bluesky.
+pub fn is_static_web_image(image_bytes: &[u8]) -> bool {
+    match image::guess_format(image_bytes) {
+        Ok(ImageFormat::Jpeg) => true,
+        Ok(ImageFormat::Png) => PngDecoder::new(Cursor::new(image_bytes))
+            .and_then(|decoder| decoder.is_apng())
+            .is_ok_and(|animated| !animated),
+        Ok(ImageFormat::WebP) => {
+            WebPDecoder::new(Cursor::new(image_bytes)).is_ok_and(|decoder| !decoder.has_animation())
+        }
+        _ => false,
+    }
+}
+
+/// Decode an image, using the first frame of animated images.
+///
+/// SVG images are rasterized so their largest side is `svg_size` pixels.
+fn decode_image(image_bytes: &[u8], svg_size: u32) -> anyhow::Result<DynamicImage> {
+    if is_svg(image_bytes) {
+        return rasterize_svg(image_bytes, svg_size);
+    }
+    match image::guess_format(image_bytes)? {
+        ImageFormat::Avif => decode_avif(image_bytes),
+        // The decoders of animated GIF, PNG and WebP images only read the first frame.
+        format => Ok(image::load_from_memory_with_format(image_bytes, format)?),
+    }
+}
+
+/// Fit an image to the target aspect ratio and encode it so it fits within the limits.
+///
+/// Returns `None` if the image is still over the size limit at the lowest quality.
+pub fn prepare_image(
+    image_bytes: &[u8],
+    fit: ThumbnailFit,
+    target_aspect: f32,
+    options: &ImageOptions,
+) -> anyhow::Result<Option<PreparedImage>> {
+    let mut img = fit_to_aspect_ratio(
+        decode_image(image_bytes, options.max_dimension)?,
+        fit,
+        target_aspect,
+        options.max_dimension,
+    );
+    let (width, height) = img.dimensions();
+    if width > options.max_dimension || height > options.max_dimension {
+        img = img.resize(
+            options.max_dimension,
+            options.max_dimension,
+            FilterType::Lanczos3,
+        );
+    }
+
+    let (width, height) = img.dimensions();
+    let img = flatten(&img);
+    for quality in JPEG_QUALITIES {
+        let mut bytes = Vec::new();
+        img.write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality))?;
+        if bytes.len() as u64 <= options.max_bytes {
+            return Ok(Some(PreparedImage {
+                bytes,
+                width,
+                height,
+            }));
+        }
+        debug!(
+            "Image is {} bytes at quality {quality}, which is over the limit of {}",
+            bytes.len(),
+            options.max_bytes
+        );
+    }
+    Ok(None)
+}
+
+/// Convert an image to RGB, placing any transparent areas on a white background.
+fn flatten(img: &DynamicImage) -> RgbImage {
+    let rgba = img.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |channel: u8| {
+            ((u16::from(channel) * u16::from(a) + 255 * u16::from(255 - a)) / 255) as u8
+        };
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+/// Fit an image to an aspect ratio without stretching it.
+///
+/// Padded images are shrunk first if needed, so that the padded image is at most `max_dimension`
+/// pixels on each side.
+pub fn fit_to_aspect_ratio(
+    img: DynamicImage,
+    fit: ThumbnailFit,
+    target_aspect: f32,
+    max_dimension: u32,
+) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return img;
+    }
+
+    let (crop_width, crop_height) = crop_size(width, height, target_aspect);
+    if (crop_width, crop_height) == (width, height) {
+        return img;
+    }
+
+    match fit {
+        ThumbnailFit::Keep => img,
+        ThumbnailFit::Crop => img.crop_imm(
+            (width - crop_width) / 2,
+            (height - crop_height) / 2,
+            crop_width,
+            crop_height,
+        ),
+        ThumbnailFit::Entropy => {
+            let (x, y) = entropy_crop_offset(&img, crop_width, crop_height);
+            img.crop_imm(x, y, crop_width, crop_height)
+        }
+        ThumbnailFit::PadSolid | ThumbnailFit::PadBlur => {
+            // Shrink the image before padding it, as the canvas can be much larger than the image.
+            let (pad_width, pad_height) = pad_size(width, height, target_aspect);
+            let largest_side = pad_width.max(pad_height);
+            let img = if largest_side > max_dimension {
+                let scale = max_dimension as f32 / largest_side as f32;
+                img.resize(
+                    ((width as f32 * scale) as u32).max(1),
+                    ((height as f32 * scale) as u32).max(1),
+                    FilterType::Lanczos3,
+                )
+            } else {
+                img
+            };
+            let (width, height) = img.dimensions();
+            let (pad_width, pad_height) = pad_size(width, height, target_aspect);
+
+            let background = if fit == ThumbnailFit::PadSolid {
+                RgbaImage::from_pixel(pad_width, pad_height, Rgba([0, 0, 0, 255]))
+            } else {
+                // Blurring a small copy and scaling it up is much faster than blurring the full image.
+                let (crop_width, crop_height) = crop_size(width, height, target_aspect);
+                img.crop_imm(
+                    (width - crop_width) / 2,
+                    (height - crop_height) / 2,
+                    crop_width,
+                    crop_height,
+                )
+                .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+                .blur(8.0)
+                .resize_exact(pad_width, pad_height, FilterType::Triangle)
+                .to_rgba8()
+            };
+            pad(background, &img)
+        }
+    }
+}
+
+/// The size of the largest part of an image with the target aspect ratio.
+fn crop_size(width: u32, height: u32, target_aspect: f32) -> (u32, u32) {
+    if width as f32 / height as f32 > target_aspect {
+        (((height as f32 * target_aspect) as u32).max(1), height)
+    } else {
+        (width, ((width as f32 / target_aspect) as u32).max(1))
+    }
+}
+
+/// The size of the smallest canvas with the target aspect ratio that holds the whole image.
+fn pad_size(width: u32, height: u32, target_aspect: f32) -> (u32, u32) {
+    if width as f32 / height as f32 > target_aspect {
+        (width, ((width as f32 / target_aspect) as u32).max(1))
+    } else {
+        (((height as f32 * target_aspect) as u32).max(1), height)
+    }
+}
+
+/// Place an image in the middle of a background.
+fn pad(mut background: RgbaImage, img: &DynamicImage) -> DynamicImage {
+    let x = (background.width() - img.width()) / 2;
+    let y = (background.height() - img.height()) / 2;
+    imageops::overlay(&mut background, &img.to_rgba8(), x.into(), y.into());
+    DynamicImage::ImageRgba8(background)
+}
+
+/// Find the position of the crop with the given size that has the most detail, measured by the
+/// entropy of its brightness.
+fn entropy_crop_offset(img: &DynamicImage, crop_width: u32, crop_height: u32) -> (u32, u32) {
+    let (width, height) = img.dimensions();
+    let sample = img
+        .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+        .to_luma8();
+    let scale = sample.width() as f32 / width as f32;
+    let sample_crop_width = ((crop_width as f32 * scale) as u32).clamp(1, sample.width());
+    let sample_crop_height = ((crop_height as f32 * scale) as u32).clamp(1, sample.height());
+
+    let entropy = |x, y| {
+        let mut histogram = [0u32; 256];
+        let view = imageops::crop_imm(&sample, x, y, sample_crop_width, sample_crop_height);
+        for pixel in view.to_image().pixels() {
+            histogram[usize::from(pixel.0[0])] += 1;
+        }
+        let total = (sample_crop_width * sample_crop_height) as f32;
+        histogram
+            .iter()
+            .filter(|count| **count > 0)
+            .map(|count| {
+                let probability = *count as f32 / total;
+                -probability * probability.log2()
+            })
+            .sum::<f32>()
+    };
+
+    // Only one of the axes is cropped, so the crop only moves along that one.
+    let max_x = sample.width() - sample_crop_width;
+    let max_y = sample.height() - sample_crop_height;
+    let (_, best_x, best_y) = (0..=ENTROPY_CROP_STEPS)
+        .map(|step| {
+            let x = max_x * step / ENTROPY_CROP_STEPS;
+            let y = max_y * step / ENTROPY_CROP_STEPS;
+            (entropy(x, y), x, y)
+        })
+        .max_by(|(a, ..), (b, ..)| a.total_cmp(b))
+        .unwrap_or_default();
+
+    (
+        ((best_x as f32 / scale) as u32).min(width - crop_width),
+        ((best_y as f32 / scale) as u32).min(height - crop_height),
+    )
+}
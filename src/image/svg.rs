@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use image::{DynamicImage, RgbaImage};
+use resvg::{
+    tiny_skia::{Pixmap, Transform},
+    usvg::{
+        Options, Tree,
+        fontdb::{Database, Family, Query},
+    },
+};
+use std::sync::{Arc, LazyLock};
+
+/// The fonts installed on the system, which are used to render text in SVG images.
+///
+/// Loading them is slow, so it is only done once.
+static FONTS: LazyLock<Arc<Database>> = LazyLock::new(|| {
+    let mut fonts = Database::new();
+    fonts.load_system_fonts();
+    // Text falls back to the serif font, which defaults to one that servers rarely have installed.
+    let has_serif = fonts
+        .query(&Query {
+            families: &[Family::Serif],
+            ..Default::default()
+        })
+        .is_some();
+    let fallback = fonts
+        .faces()
+        .find_map(|face| face.families.first())
+        .map(|(family, _)| family.clone());
+    if !has_serif && let Some(family) = fallback {
+        fonts.set_serif_family(family);
+    }
+    Arc::new(fonts)
+});
+
+/// Check whether the data looks like an SVG document, which has no magic bytes to detect it by.
+pub fn is_svg(image_bytes: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(&image_bytes[..image_bytes.len().min(1024)]);
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    (start.starts_with("<?xml") || start.starts_with("<!DOCTYPE") || start.starts_with("<svg"))
+        && start.contains("<svg")
+}
+
+/// Rasterize an SVG image so its largest side is `size` pixels.
+pub fn rasterize_svg(image_bytes: &[u8], size: u32) -> Result<DynamicImage> {
+    let mut options = Options {
+        fontdb: FONTS.clone(),
+        ..Default::default()
+    };
+    // Images referenced by path would be read from the local filesystem, so only embedded ones are allowed.
+    options.image_href_resolver.resolve_string = Box::new(|_, _| None);
+    let tree = Tree::from_data(image_bytes, &options)?;
+
+    let scale = size as f32 / tree.size().width().max(tree.size().height());
+    let width = ((tree.size().width() * scale).round() as u32).max(1);
+    let height = ((tree.size().height() * scale).round() as u32).max(1);
+    let mut pixmap = Pixmap::new(width, height).context("SVG image has an invalid size")?;
+    resvg::render(
+        &tree,
+        Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    let image = RgbaImage::from_raw(width, height, pixmap.take_demultiplied())
+        .context("rendered SVG image has an unexpected size")?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
@@ -1,8 +1,15 @@
 mod bsky;
 mod commands;
+mod compose;
+mod config;
 mod database;
+mod filter;
 mod image;
+mod outbox;
+mod ratelimit;
 mod rss;
+mod shutdown;
+mod video;
 
 use anyhow::Result;
 use clap::Parser;
@@ -0,0 +1,218 @@
+use crate::bsky::{BlueskyClient, PostData, PostRef};
+use crate::database::{Database, NewOutboxEntry, OutboxEntry, PostRecord};
+use crate::ratelimit::{RateLimiter, Slot};
+use crate::shutdown::Shutdown;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info, warn};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// The longest time to wait between attempts of the same post.
+const MAX_RETRY_DELAY: Duration = Duration::hours(6);
+
+/// A database-backed queue of posts that failed to be created or were held back by the rate limits.
+pub struct Outbox {
+    database: Arc<Database>,
+    rate_limiter: Arc<RateLimiter>,
+    max_attempts: u32,
+    retry_delay: Duration,
+}
+
+impl Outbox {
+    pub fn new(
+        database: Arc<Database>,
+        rate_limiter: Arc<RateLimiter>,
+        max_attempts: u32,
+        retry_delay: Duration,
+    ) -> Self {
+        Self {
+            database,
+            rate_limiter,
+            max_attempts,
+            retry_delay,
+        }
+    }
+
+    /// The delay before the next attempt of a post that has already been attempted `attempts` times.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let multiplier = 2_i32.saturating_pow(attempts.saturating_sub(1));
+        self.retry_delay
+            .checked_mul(multiplier)
+            .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+    }
+
+    /// Add a post that failed to be created for an account to the outbox.
+    pub async fn enqueue(
+        &self,
+        url: &str,
+        account: &str,
+        feed_url: Option<&str>,
+        entry_date: Option<DateTime<Utc>>,
+        post_data: &PostData,
+        error: &anyhow::Error,
+    ) -> Result<()> {
+        let next_attempt_at = Utc::now() + self.backoff(1);
+        info!(
+            "Adding '{url}' for account '{account}' to the outbox, retrying at {next_attempt_at}"
+        );
+        let entry = NewOutboxEntry {
+            url,
+            account,
+            feed_url,
+            entry_date,
+            post_data: &serde_json::to_string(post_data)?,
+        };
+        self.database
+            .insert_outbox_entry(&entry, next_attempt_at, &format!("{error:#}"))
+            .await
+    }
+
+    /// Add a post that has been held back by the rate limits to the outbox, to be created at `next_slot`.
+    pub async fn defer(
+        &self,
+        url: &str,
+        account: &str,
+        feed_url: Option<&str>,
+        entry_date: Option<DateTime<Utc>>,
+        post_data: &PostData,
+        next_slot: DateTime<Utc>,
+    ) -> Result<()> {
+        info!(
+            "Account '{account}' has reached its posting limits, queueing '{url}' until {next_slot}"
+        );
+        let entry = NewOutboxEntry {
+            url,
+            account,
+            feed_url,
+            entry_date,
+            post_data: &serde_json::to_string(post_data)?,
+        };
+        self.database
+            .insert_deferred_outbox_entry(&entry, next_slot)
+            .await
+    }
+
+    /// Add a post for an account that already has posts waiting in the outbox, so that the
+    /// outbox creates them all in the order their entries were published.
+    pub async fn queue(
+        &self,
+        url: &str,
+        account: &str,
+        feed_url: Option<&str>,
+        entry_date: Option<DateTime<Utc>>,
+        post_data: &PostData,
+    ) -> Result<()> {
+        info!("Account '{account}' has posts waiting in the outbox, queueing '{url}' with them");
+        let entry = NewOutboxEntry {
+            url,
+            account,
+            feed_url,
+            entry_date,
+            post_data: &serde_json::to_string(post_data)?,
+        };
+        self.database
+            .insert_deferred_outbox_entry(&entry, Utc::now())
+            .await
+    }
+
+    /// Attempt to create every post in the outbox that is due to be retried.
+    ///
+    /// Stops early without attempting the remaining posts if shutdown is triggered.
+    pub async fn retry_due(
+        &self,
+        bsky_clients: &BTreeMap<String, Arc<BlueskyClient>>,
+        shutdown: &Shutdown,
+    ) -> Result<()> {
+        for entry in self.database.get_due_outbox_entries(Utc::now()).await? {
+            if shutdown.is_triggered() {
+                break;
+            }
+            let Some(bsky_client) = bsky_clients.get(&entry.account) else {
+                warn!(
+                    "Outbox entry for '{}' belongs to account '{}' which is not logged in, skipping",
+                    entry.url, entry.account
+                );
+                continue;
+            };
+            let feed_url = entry.feed_url.as_deref();
+            let _slot = match self.rate_limiter.acquire(&entry.account, feed_url).await? {
+                Slot::Available(slot) => slot,
+                Slot::Deferred(next_slot) => {
+                    self.database
+                        .defer_outbox_entry(entry.id, next_slot)
+                        .await?;
+                    continue;
+                }
+            };
+            if entry.attempts == 0 {
+                info!(
+                    "Posting queued post '{}' for account '{}'",
+                    entry.url, entry.account
+                );
+            } else {
+                info!(
+                    "Retrying post '{}' for account '{}' (attempt {})",
+                    entry.url,
+                    entry.account,
+                    entry.attempts + 1
+                );
+            }
+            match self.attempt(&entry, bsky_client).await {
+                Ok(thread) => {
+                    if let Err(err) = self.rate_limiter.record(&entry.account, feed_url).await {
+                        warn!(
+                            "Failed to record post '{}' for account '{}': {err:?}",
+                            entry.url, entry.account
+                        );
+                    }
+                    self.database.delete_outbox_entry(entry.id).await?;
+                    for record in PostRecord::from_thread(&entry.url, &entry.account, thread) {
+                        if let Err(err) = self.database.insert_post_record(&record).await {
+                            error!(
+                                "Failed to store post record {} for '{}' in the database: {err:?}",
+                                record.uri, record.url
+                            );
+                        }
+                    }
+                }
+                Err(err) => self.record_failure(&entry, &err).await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn attempt(
+        &self,
+        entry: &OutboxEntry,
+        bsky_client: &BlueskyClient,
+    ) -> Result<Vec<PostRef>> {
+        let post_data: PostData = serde_json::from_str(&entry.post_data)
+            .context("failed to deserialize outbox post data")?;
+        bsky_client.post(&post_data).await
+    }
+
+    async fn record_failure(&self, entry: &OutboxEntry, err: &anyhow::Error) -> Result<()> {
+        let attempts = entry.attempts + 1;
+        let message = format!("{err:#}");
+        if attempts >= i64::from(self.max_attempts) {
+            error!(
+                "Post '{}' for account '{}' failed after {attempts} attempts and will not be retried: {err:?}",
+                entry.url, entry.account
+            );
+            return self
+                .database
+                .mark_outbox_entry_dead(entry.id, attempts, &message)
+                .await;
+        }
+
+        let next_attempt_at =
+            Utc::now() + self.backoff(u32::try_from(attempts).unwrap_or(u32::MAX));
+        warn!(
+            "Post '{}' for account '{}' failed again, retrying at {next_attempt_at}: {err:?}",
+            entry.url, entry.account
+        );
+        self.database
+            .reschedule_outbox_entry(entry.id, attempts, next_attempt_at, &message)
+            .await
+    }
+}
@@ -0,0 +1,198 @@
+use crate::database::Database;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+use std::{collections::BTreeMap, num::NonZeroU32, sync::Arc};
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Limits on the number of posts created within an hour and within a day.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PostLimits {
+    pub per_hour: Option<NonZeroU32>,
+    pub per_day: Option<NonZeroU32>,
+}
+
+impl PostLimits {
+    /// Get the earliest time another post fits within the limits, if the limits have been reached.
+    ///
+    /// `times` are the times of the posts created in the last day, oldest first.
+    fn next_slot(&self, times: &[DateTime<Utc>], now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        [
+            (self.per_hour, Duration::hours(1)),
+            (self.per_day, Duration::days(1)),
+        ]
+        .into_iter()
+        .filter_map(|(limit, window)| {
+            let limit = limit?.get() as usize;
+            let recent = &times[times.partition_point(|time| *time <= now - window)..];
+            // Another post fits once enough of the recent posts have left the window.
+            (recent.len() >= limit).then(|| recent[recent.len() - limit] + window)
+        })
+        .max()
+    }
+}
+
+/// Whether a post can be created now.
+pub enum Slot<'a> {
+    /// The post can be created now. Other posts by the same account are held back until this is dropped.
+    Available(MutexGuard<'a, ()>),
+    /// The post has to wait until the given time.
+    Deferred(DateTime<Utc>),
+}
+
+/// Paces the posts created by every account so they stay within the configured limits.
+///
+/// Posts are counted from the database, so the limits hold across restarts.
+pub struct RateLimiter {
+    database: Arc<Database>,
+    min_post_gap: Duration,
+    accounts: BTreeMap<String, PostLimits>,
+    feeds: BTreeMap<String, PostLimits>,
+    /// One lock per account, so that a slow post only holds back other posts by the same account.
+    locks: BTreeMap<String, Mutex<()>>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        database: Arc<Database>,
+        min_post_gap: Duration,
+        accounts: BTreeMap<String, PostLimits>,
+        feeds: BTreeMap<String, PostLimits>,
+    ) -> Self {
+        let locks = accounts
+            .keys()
+            .map(|account| (account.clone(), Mutex::new(())))
+            .collect();
+        Self {
+            database,
+            min_post_gap,
+            accounts,
+            feeds,
+            locks,
+        }
+    }
+
+    /// Check whether an account can create a post for a feed now.
+    ///
+    /// When it can, the returned slot should be held until the post has been recorded
+    /// so that posts created at the same time by other feeds of the account are counted.
+    pub async fn acquire(&self, account: &str, feed_url: Option<&str>) -> Result<Slot<'_>> {
+        let guard = self
+            .locks
+            .get(account)
+            .with_context(|| format!("account '{account}' is not configured"))?
+            .lock()
+            .await;
+        let now = Utc::now();
+        let since = now - Duration::days(1);
+
+        let account_times = self.database.get_account_post_times(account, since).await?;
+        let mut next_slot = account_times.last().map(|last| *last + self.min_post_gap);
+        if let Some(limits) = self.accounts.get(account) {
+            next_slot = next_slot.max(limits.next_slot(&account_times, now));
+        }
+        if let Some(feed_url) = feed_url
+            && let Some(limits) = self.feeds.get(feed_url)
+        {
+            let feed_times = self
+                .database
+                .get_feed_post_times(feed_url, account, since)
+                .await?;
+            next_slot = next_slot.max(limits.next_slot(&feed_times, now));
+        }
+
+        Ok(match next_slot.filter(|slot| *slot > now) {
+            Some(slot) => {
+                debug!("Account '{account}' cannot post again until {slot}");
+                Slot::Deferred(slot)
+            }
+            None => Slot::Available(guard),
+        })
+    }
+
+    /// Record that an account has created a post for a feed.
+    pub async fn record(&self, account: &str, feed_url: Option<&str>) -> Result<()> {
+        self.database
+            .insert_post_log_entry(account, feed_url, Utc::now())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(per_hour: u32, per_day: u32) -> PostLimits {
+        PostLimits {
+            per_hour: NonZeroU32::new(per_hour),
+            per_day: NonZeroU32::new(per_day),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    fn ago(minutes: i64) -> DateTime<Utc> {
+        now() - Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn next_slot_without_limits() {
+        let times = [ago(3), ago(2), ago(1)];
+        assert_eq!(PostLimits::default().next_slot(&times, now()), None);
+    }
+
+    #[test]
+    fn next_slot_below_limits() {
+        let times = [ago(30)];
+        assert_eq!(limits(2, 10).next_slot(&times, now()), None);
+        assert_eq!(limits(2, 10).next_slot(&[], now()), None);
+    }
+
+    #[test]
+    fn next_slot_waits_for_the_hour_window() {
+        let times = [ago(59), ago(30)];
+        assert_eq!(limits(2, 0).next_slot(&times, now()), Some(ago(-1)));
+        // Only the posts that have to leave the window for the limit to be met are waited for.
+        let times = [ago(50), ago(40), ago(30)];
+        assert_eq!(limits(2, 0).next_slot(&times, now()), Some(ago(-20)));
+    }
+
+    #[test]
+    fn next_slot_at_the_hour_boundary() {
+        // A post made exactly an hour ago has left the window.
+        let times = [ago(60), ago(30)];
+        assert_eq!(limits(2, 0).next_slot(&times, now()), None);
+        let times = [
+            now() - Duration::minutes(60) + Duration::seconds(1),
+            ago(30),
+        ];
+        assert_eq!(
+            limits(2, 0).next_slot(&times, now()),
+            Some(now() + Duration::seconds(1))
+        );
+    }
+
+    #[test]
+    fn next_slot_waits_for_the_day_window() {
+        let times = [ago(23 * 60), ago(20 * 60), ago(2 * 60)];
+        assert_eq!(limits(2, 3).next_slot(&times, now()), Some(ago(-60)));
+    }
+
+    #[test]
+    fn next_slot_at_the_day_boundary() {
+        // A post made exactly a day ago has left the window.
+        let times = [ago(24 * 60), ago(20 * 60), ago(2 * 60)];
+        assert_eq!(limits(0, 3).next_slot(&times, now()), None);
+    }
+
+    #[test]
+    fn next_slot_uses_the_latest_of_both_windows() {
+        let times = [ago(23 * 60), ago(50), ago(10)];
+        // The hourly limit is met at 10 minutes from now, the daily one in an hour.
+        assert_eq!(limits(2, 3).next_slot(&times, now()), Some(ago(-60)));
+        let times = [ago(30 * 60), ago(50), ago(10)];
+        assert_eq!(limits(2, 3).next_slot(&times, now()), Some(ago(-10)));
+    }
+}
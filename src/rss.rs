@@ -1,26 +1,80 @@
-use crate::database::Database;
+use crate::config::{DateSource, FeedOptions, UpdateMode};
+use crate::database::{Database, FeedValidators, PostedKeys};
+use crate::filter::EntryFilter;
 use anyhow::{Result, bail};
 use chrono::{DateTime, Duration, Utc};
-use feed_rs::model::Feed;
-use log::debug;
-use reqwest::{Client, Url};
-use std::sync::Arc;
+use feed_rs::model::{Entry, Feed};
+use log::{debug, info};
+use reqwest::{
+    Client, StatusCode, Url,
+    header::{ETAG, HeaderMap, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+/// A feed entry that has not been posted yet.
+#[derive(Debug, Clone)]
+pub struct UnpostedEntry {
+    pub entry: Entry,
+    /// The link the entry will be posted with.
+    pub link: String,
+    /// The canonicalized form of the link, used to recognise the entry under a different link.
+    pub url_key: String,
+    /// The URL the entry was stored under when it was first posted, if it has been posted.
+    pub posted_url: Option<String>,
+    /// The date of the entry, taken from the first available date source.
+    pub date: DateTime<Utc>,
+}
+
+/// A fetched feed alongside the entries from it that have not been posted yet.
+#[derive(Debug)]
+pub struct FetchedFeed {
+    /// The feed metadata. The entries are moved into `entries` and `updated_entries`.
+    pub feed: Feed,
+    pub entries: Vec<UnpostedEntry>,
+    /// Entries that have already been posted but have changed since.
+    /// Only populated when updates are being tracked.
+    pub updated_entries: Vec<UnpostedEntry>,
+    /// The links of every entry in the feed and their canonicalized forms, whether posted or not.
+    pub links: HashSet<String>,
+    /// The IDs of every entry in the feed, whether posted or not.
+    pub entry_ids: HashSet<String>,
+}
 
 pub struct RssHandler {
     client: Arc<Client>,
     database: Arc<Database>,
     feed_url: Url,
     backfill_window: Duration,
+    date_sources: Vec<DateSource>,
+    track_updates: bool,
+    canonicalize_urls: bool,
+    tracking_params: Vec<String>,
+    dedup_entry_ids: bool,
+    filter: EntryFilter,
+    /// A hash of the post template, so entries that could not be posted are tried again when it changes.
+    post_template_hash: String,
     fetch_after_date: DateTime<Utc>,
+    pending_validators: Option<FeedValidators>,
+    /// Whether the database should be left untouched, so that a real run still sees every entry.
+    dry_run: bool,
+    /// The first seen dates of entries that are not in the database, kept in memory during dry runs.
+    dry_run_first_seen: HashMap<String, DateTime<Utc>>,
 }
 
 impl RssHandler {
     pub fn new(
-        feed_url: Url,
-        backfill_window: Duration,
+        feed: &FeedOptions,
+        filter: EntryFilter,
         database: Arc<Database>,
         reqwest_client: Arc<Client>,
+        dry_run: bool,
     ) -> Self {
+        let feed_url = feed.url.clone();
+        let backfill_window = Duration::hours(feed.backdate_hours as i64);
         let filter_date = Utc::now() - backfill_window;
         debug!(
             "Initializing RSS handler for {feed_url} with starting date of {filter_date} (backfill window: {backfill_window})"
@@ -31,6 +85,16 @@ impl RssHandler {
             feed_url,
             fetch_after_date: filter_date,
             backfill_window,
+            date_sources: feed.date_sources.clone(),
+            track_updates: feed.update_mode != UpdateMode::Off,
+            canonicalize_urls: feed.canonicalize_urls,
+            tracking_params: feed.tracking_params.clone(),
+            dedup_entry_ids: feed.dedup_entry_ids,
+            filter,
+            post_template_hash: format!("{:x}", Sha256::digest(&feed.post_template)),
+            pending_validators: None,
+            dry_run,
+            dry_run_first_seen: HashMap::new(),
         }
     }
 
@@ -38,9 +102,39 @@ impl RssHandler {
         &self.feed_url
     }
 
-    pub async fn fetch_unposted(&mut self) -> Result<Feed> {
+    /// Fetch the feed and return the entries that have not been posted yet.
+    ///
+    /// Returns `None` if the feed has not been modified since it was last processed.
+    pub async fn fetch_unposted(&mut self) -> Result<Option<FetchedFeed>> {
         let content = {
-            let response = self.client.get(self.feed_url.as_ref()).send().await?;
+            let mut headers = HeaderMap::new();
+            if let Some(validators) = self
+                .database
+                .get_feed_validators(self.feed_url.as_str())
+                .await?
+            {
+                if let Some(etag) = validators.etag.and_then(|v| v.parse().ok()) {
+                    headers.insert(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = validators.last_modified.and_then(|v| v.parse().ok()) {
+                    headers.insert(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+
+            let response = self
+                .client
+                .get(self.feed_url.as_ref())
+                .headers(headers)
+                .send()
+                .await?;
+            if response.status() == StatusCode::NOT_MODIFIED {
+                debug!(
+                    "Feed {} has not been modified since last fetch",
+                    self.feed_url
+                );
+                self.fetch_after_date = Utc::now() - self.backfill_window;
+                return Ok(None);
+            }
             if !response.status().is_success() {
                 bail!(
                     "got unsuccessful status code when requesting feed {}: {}",
@@ -48,43 +142,489 @@ impl RssHandler {
                     response.status()
                 )
             }
+
+            let header_value = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            self.pending_validators = Some(FeedValidators {
+                etag: header_value(ETAG),
+                last_modified: header_value(LAST_MODIFIED),
+            });
             response.bytes().await?
         };
 
         let mut feed = feed_rs::parser::parse(&content[..])?;
+
+        // Entries from a feed that has never been seen before are given the date of the
+        // backfill window so that undated entries are not all posted on the first fetch.
+        let first_seen_date = if self.date_sources.contains(&DateSource::FirstSeen)
+            && self.dry_run_first_seen.is_empty()
+            && !self.database.has_seen_feed(self.feed_url.as_str()).await?
+        {
+            self.fetch_after_date
+        } else {
+            Utc::now()
+        };
+
         let mut new_entries = vec![];
-        for mut item in feed.entries {
-            // Only count posts that are after the filter date.
-            let Some(pub_date) = item.published else {
+        let mut updated_entries = vec![];
+        let mut links = HashSet::new();
+        let mut entry_ids = HashSet::new();
+        // New entries are not in the database yet, so entries repeated within the feed are
+        // recognised by the keys of the new entries so far.
+        let mut new_url_keys = HashSet::new();
+        let mut new_entry_ids = HashSet::new();
+        for mut item in std::mem::take(&mut feed.entries) {
+            entry_ids.insert(item.id.clone());
+            let date = self.entry_date(&item, first_seen_date).await?;
+            let Some(link) = select_link(&mut item, Some(&self.feed_url)) else {
+                debug!(
+                    "Entry '{}' did not have any links attached, it will be skipped",
+                    item.id
+                );
                 continue;
             };
-            if pub_date <= self.fetch_after_date {
+            let url_key = self.url_key(&link);
+            links.insert(link.clone());
+            links.insert(url_key.clone());
+
+            // Posted entries are checked for changes regardless of their date, as editing
+            // an entry does not always change the date it is posted with.
+            let keys = PostedKeys {
+                url: &link,
+                url_key: &url_key,
+                entry: self
+                    .dedup_entry_ids
+                    .then_some((self.feed_url.as_str(), item.id.as_str())),
+            };
+            if let Some(posted_url) = self.database.find_posted_url(keys).await? {
+                if self.track_updates && self.has_changed(&posted_url, &item, &link).await? {
+                    updated_entries.push(UnpostedEntry {
+                        entry: item,
+                        link,
+                        url_key,
+                        posted_url: Some(posted_url),
+                        date: date.unwrap_or_else(Utc::now),
+                    });
+                } else if posted_url != link {
+                    debug!(
+                        "Entry '{link}' was already posted as '{posted_url}', it will be skipped"
+                    );
+                }
                 continue;
             }
 
-            // Prefer the first post link that is from the same domain as the rss feed.
-            item.links.sort_by_key(|link| {
-                Url::parse(&link.href)
-                    .ok()
-                    .map(|url| match (url.domain(), self.feed_url.domain()) {
-                        (Some(link_domain), Some(feed_domain)) => link_domain == feed_domain,
-                        _ => false,
-                    })
-                    .unwrap_or(false)
-            });
-            item.links.reverse();
-
-            // Get the first link, if any
-            let Some(link) = item.links.first() else {
+            // Only count posts that are after the filter date.
+            let Some(date) = date else {
+                debug!(
+                    "Entry '{}' has no date from any configured date source, it will be skipped",
+                    item.id
+                );
                 continue;
             };
-            if self.database.has_posted_url(&link.href).await? {
+            if date <= self.fetch_after_date {
+                continue;
+            }
+            if self
+                .database
+                .is_entry_skipped(
+                    self.feed_url.as_str(),
+                    &item.id,
+                    &self.unpostable_fingerprint(&item, &link),
+                )
+                .await?
+            {
+                debug!("Entry '{link}' could not be posted before, it will be skipped");
+                continue;
+            }
+            if self.is_filtered(&item, &link).await? {
+                continue;
+            }
+            if new_url_keys.contains(&url_key)
+                || (self.dedup_entry_ids && new_entry_ids.contains(&item.id))
+            {
+                debug!("Entry '{link}' is repeated in the feed, it will only be posted once");
                 continue;
             }
-            new_entries.push(item);
+            new_url_keys.insert(url_key.clone());
+            new_entry_ids.insert(item.id.clone());
+            new_entries.push(UnpostedEntry {
+                entry: item,
+                link,
+                url_key,
+                posted_url: None,
+                date,
+            });
         }
+        // Feeds usually list their newest entries first, but a backlog should be posted in the order
+        // it was published. Entries with the same date are ordered by link so the order stays stable.
+        new_entries.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.link.cmp(&b.link)));
         self.fetch_after_date = Utc::now() - self.backfill_window;
-        feed.entries = new_entries;
-        Ok(feed)
+        Ok(Some(FetchedFeed {
+            feed,
+            entries: new_entries,
+            updated_entries,
+            links,
+            entry_ids,
+        }))
+    }
+
+    /// Get the key used to recognise an entry under a different form of its link.
+    pub fn url_key(&self, link: &str) -> String {
+        if self.canonicalize_urls {
+            canonicalize_url(link, &self.tracking_params)
+        } else {
+            link.to_string()
+        }
+    }
+
+    /// Check whether an entry is skipped by the filter rules, recording it if it has not been already.
+    async fn is_filtered(&self, entry: &Entry, link: &str) -> Result<bool> {
+        if self.filter.is_empty() {
+            return Ok(false);
+        }
+        let feed_url = self.feed_url.as_str();
+        if self
+            .database
+            .is_entry_skipped(feed_url, &entry.id, self.filter.fingerprint())
+            .await?
+        {
+            return Ok(true);
+        }
+        let Some(rule) = self.filter.check(entry, link) else {
+            return Ok(false);
+        };
+        info!("Skipping entry '{link}' as it matched {rule}");
+        if self.dry_run {
+            return Ok(true);
+        }
+        self.database
+            .insert_skipped_entry(
+                feed_url,
+                &entry.id,
+                link,
+                &rule,
+                self.filter.fingerprint(),
+                Utc::now(),
+            )
+            .await?;
+        Ok(true)
+    }
+
+    /// Record that an entry can never be posted as it is, so that it is skipped until either the
+    /// entry or the post template changes.
+    pub async fn skip_unpostable(&self, unposted: &UnpostedEntry, reason: &str) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        self.database
+            .insert_skipped_entry(
+                self.feed_url.as_str(),
+                &unposted.entry.id,
+                &unposted.link,
+                reason,
+                &self.unpostable_fingerprint(&unposted.entry, &unposted.link),
+                Utc::now(),
+            )
+            .await
+    }
+
+    fn unpostable_fingerprint(&self, entry: &Entry, link: &str) -> String {
+        format!(
+            "unpostable:{}:{}",
+            self.post_template_hash,
+            content_hash(entry, link)
+        )
+    }
+
+    /// Check whether an entry that has already been posted from this feed has changed since.
+    ///
+    /// Entries posted before their content was tracked have their current content stored
+    /// instead, so that only changes made from now on are picked up.
+    async fn has_changed(&self, posted_url: &str, entry: &Entry, link: &str) -> Result<bool> {
+        let Some(posted) = self.database.get_posted_url(posted_url).await? else {
+            return Ok(false);
+        };
+        if posted.feed_url.as_deref() != Some(self.feed_url.as_str())
+            || posted.retracted_at.is_some()
+        {
+            return Ok(false);
+        }
+        let content_hash = content_hash(entry, link);
+        let Some(posted_hash) = posted.content_hash else {
+            if self.dry_run {
+                return Ok(false);
+            }
+            self.database
+                .update_posted_entry_version(
+                    posted_url,
+                    posted.title.as_deref(),
+                    &content_hash,
+                    entry.updated,
+                )
+                .await?;
+            return Ok(false);
+        };
+        // Updated dates are stored in whole seconds, so they are compared at that precision.
+        let updated = entry.updated.map(|date| date.timestamp());
+        Ok(posted_hash != content_hash
+            || (updated.is_some()
+                && updated != posted.entry_updated_at.map(|date| date.timestamp())))
+    }
+
+    /// Get the date of an entry from the first configured date source that provides one.
+    async fn entry_date(
+        &mut self,
+        entry: &Entry,
+        first_seen_date: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        if !self.date_sources.contains(&DateSource::FirstSeen) {
+            return Ok(resolve_date(&self.date_sources, entry, None));
+        }
+        // Every entry is recorded, even those with other dates, so that later entries
+        // are not mistaken for entries from a feed that has never been seen before.
+        // Dry runs record them in memory instead.
+        let feed_url = self.feed_url.as_str();
+        let first_seen = if !self.dry_run {
+            self.database
+                .get_or_insert_first_seen(feed_url, &entry.id, first_seen_date)
+                .await?
+        } else if let Some(first_seen) = self.database.get_first_seen(feed_url, &entry.id).await? {
+            first_seen
+        } else {
+            *self
+                .dry_run_first_seen
+                .entry(entry.id.clone())
+                .or_insert(first_seen_date)
+        };
+        Ok(resolve_date(&self.date_sources, entry, Some(first_seen)))
+    }
+
+    /// Store the cache validators from the last fetch so the next request can be conditional.
+    ///
+    /// This should only be called once every entry from the last fetch has been handled, otherwise
+    /// entries that need to be retried would be hidden behind a `304 Not Modified` response.
+    pub async fn save_validators(&mut self) -> Result<()> {
+        if let Some(validators) = self.pending_validators.take() {
+            self.database
+                .set_feed_validators(self.feed_url.as_str(), &validators)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Get the date of an entry from the first date source that provides one.
+pub fn resolve_date(
+    date_sources: &[DateSource],
+    entry: &Entry,
+    first_seen: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    date_sources.iter().find_map(|source| match source {
+        DateSource::Published => entry.published,
+        DateSource::Updated => entry.updated,
+        DateSource::FirstSeen => first_seen,
+    })
+}
+
+/// Reduce a link to a form that stays the same across the common ways a feed may vary it.
+///
+/// The scheme, a leading `www.`, trailing slashes, the fragment and any tracking parameters
+/// are dropped, and the remaining query parameters are sorted. Tracking parameters ending
+/// in `*` match every parameter starting with the text before it.
+pub fn canonicalize_url(link: &str, tracking_params: &[impl AsRef<str>]) -> String {
+    let Ok(url) = Url::parse(link) else {
+        return link.to_string();
+    };
+    let Some(host) = url
+        .host_str()
+        .filter(|_| matches!(url.scheme(), "http" | "https"))
+    else {
+        return link.to_string();
+    };
+    let is_tracking = |name: &str| {
+        let name = name.to_lowercase();
+        tracking_params.iter().any(|param| {
+            let param = param.as_ref().to_lowercase();
+            match param.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == param,
+            }
+        })
+    };
+
+    let host = host.to_lowercase();
+    let mut key = host.strip_prefix("www.").unwrap_or(&host).to_string();
+    if let Some(port) = url.port() {
+        key.push_str(&format!(":{port}"));
+    }
+    key.push_str(url.path().trim_end_matches('/'));
+    let mut query = url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if !query.is_empty() {
+        query.sort();
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(query)
+            .finish();
+        key.push('?');
+        key.push_str(&query);
+    }
+    key
+}
+
+/// Check whether the page an entry linked to has been removed by its publisher.
+pub async fn is_link_gone(client: &Client, url: &str) -> Result<bool> {
+    let status = client.get(url).send().await?.status();
+    Ok(matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE))
+}
+
+/// Hash the parts of an entry that are used to build its post, including the link selected
+/// for it, used to detect changes to it.
+pub fn content_hash(entry: &Entry, link: &str) -> String {
+    let mut hasher = Sha256::new();
+    let mut field = |value: Option<&str>| {
+        let value = value.unwrap_or_default();
+        hasher.update(value.len().to_le_bytes());
+        hasher.update(value);
+    };
+    field(Some(link));
+    field(entry.title.as_ref().map(|title| title.content.as_str()));
+    field(
+        entry
+            .summary
+            .as_ref()
+            .map(|summary| summary.content.as_str()),
+    );
+    field(
+        entry
+            .content
+            .as_ref()
+            .and_then(|content| content.body.as_deref()),
+    );
+    for category in &entry.categories {
+        field(Some(&category.term));
+    }
+    for media in &entry.media {
+        for content in &media.content {
+            field(content.url.as_ref().map(Url::as_str));
+        }
+        for thumbnail in &media.thumbnails {
+            field(Some(&thumbnail.image.uri));
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Select the link an entry should be posted with.
+pub fn select_link(entry: &mut Entry, feed_url: Option<&Url>) -> Option<String> {
+    // Prefer the first post link that is from the same domain as the rss feed.
+    entry.links.sort_by_key(|link| {
+        Url::parse(&link.href)
+            .ok()
+            .map(|url| {
+                match (
+                    url.domain(),
+                    feed_url.and_then(|feed_url| feed_url.domain()),
+                ) {
+                    (Some(link_domain), Some(feed_domain)) => link_domain == feed_domain,
+                    _ => false,
+                }
+            })
+            .unwrap_or(false)
+    });
+    entry.links.reverse();
+
+    // Get the first link, if any
+    entry.links.first().map(|link| link.href.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_TRACKING_PARAMS;
+
+    fn canonicalize(link: &str) -> String {
+        canonicalize_url(link, DEFAULT_TRACKING_PARAMS)
+    }
+
+    #[test]
+    fn canonicalize_url_removes_tracking_params() {
+        assert_eq!(
+            canonicalize("https://example.com/post?utm_source=rss&UTM_Medium=feed&fbclid=abc"),
+            "example.com/post"
+        );
+        assert_eq!(
+            canonicalize("https://example.com/post?id=1&utm_campaign=launch"),
+            "example.com/post?id=1"
+        );
+        // A wildcard only matches parameters starting with its prefix.
+        assert_eq!(
+            canonicalize("https://example.com/post?utm=1&fbclid_x=2"),
+            "example.com/post?fbclid_x=2&utm=1"
+        );
+    }
+
+    #[test]
+    fn canonicalize_url_uses_custom_tracking_params() {
+        assert_eq!(
+            canonicalize_url("https://example.com/post?src=rss&id=1", &["src"]),
+            "example.com/post?id=1"
+        );
+        assert_eq!(
+            canonicalize_url("https://example.com/post?ref=a&referrer=b&id=1", &["ref*"]),
+            "example.com/post?id=1"
+        );
+    }
+
+    #[test]
+    fn canonicalize_url_folds_equivalent_forms() {
+        let key = "example.com/blog/post";
+        for link in [
+            "https://example.com/blog/post",
+            "http://example.com/blog/post",
+            "https://www.example.com/blog/post",
+            "https://WWW.Example.COM/blog/post/",
+            "http://example.com/blog/post//",
+            "https://example.com/blog/post#comments",
+        ] {
+            assert_eq!(canonicalize(link), key, "{link}");
+        }
+        // Paths are case sensitive and ports are significant.
+        assert_eq!(
+            canonicalize("https://example.com/Blog/Post"),
+            "example.com/Blog/Post"
+        );
+        assert_eq!(
+            canonicalize("https://example.com:8080/blog/post"),
+            "example.com:8080/blog/post"
+        );
+    }
+
+    #[test]
+    fn canonicalize_url_orders_query_params() {
+        assert_eq!(
+            canonicalize("https://example.com/search?q=rust&page=2&lang=en"),
+            "example.com/search?lang=en&page=2&q=rust"
+        );
+        assert_eq!(
+            canonicalize("https://example.com/search?page=2&lang=en&q=rust"),
+            canonicalize("https://example.com/search?q=rust&lang=en&page=2")
+        );
+    }
+
+    #[test]
+    fn canonicalize_url_keeps_other_links() {
+        assert_eq!(canonicalize("not a url"), "not a url");
+        assert_eq!(
+            canonicalize("mailto:someone@example.com"),
+            "mailto:someone@example.com"
+        );
     }
 }
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use tokio::sync::watch;
+
+/// A handle used by tasks to find out when the process is shutting down.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+/// Notifies every [`Shutdown`] handle that the process is shutting down.
+#[derive(Debug)]
+pub struct ShutdownTrigger {
+    sender: watch::Sender<bool>,
+}
+
+/// Create a new shutdown trigger and a handle that is notified by it.
+pub fn channel() -> (ShutdownTrigger, Shutdown) {
+    let (sender, receiver) = watch::channel(false);
+    (ShutdownTrigger { sender }, Shutdown { receiver })
+}
+
+impl Shutdown {
+    /// Whether shutdown has been triggered.
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Wait until shutdown has been triggered.
+    pub async fn wait(&mut self) {
+        // An error means the trigger was dropped, which only happens when shutting down anyway.
+        let _ = self.receiver.wait_for(|triggered| *triggered).await;
+    }
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+}
+
+/// Wait until the process receives a SIGINT or SIGTERM.
+pub async fn wait_for_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
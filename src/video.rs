@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+/// Read the duration of an MP4 video from the `mvhd` box of its `moov` box.
+///
+/// Returns `None` if the video is not a valid MP4 or does not declare a duration.
+pub fn mp4_duration(video: &[u8]) -> Option<Duration> {
+    let moov = find_box(video, b"moov")?;
+    let mvhd = find_box(moov, b"mvhd")?;
+    let version = *mvhd.first()?;
+    // The version and flags are followed by the creation and modification times, which are
+    // 32-bit in version 0 and 64-bit in version 1, then the timescale and duration.
+    let (timescale, duration) = match version {
+        0 => (read_u32(mvhd, 12)?, u64::from(read_u32(mvhd, 16)?)),
+        1 => (read_u32(mvhd, 20)?, read_u64(mvhd, 24)?),
+        _ => return None,
+    };
+    // A duration of all ones means the duration is unknown.
+    if timescale == 0 || duration == u64::MAX || (version == 0 && duration == u64::from(u32::MAX)) {
+        return None;
+    }
+    Some(Duration::from_secs_f64(
+        duration as f64 / f64::from(timescale),
+    ))
+}
+
+/// Find the contents of the first box with the given type within a sequence of boxes.
+fn find_box<'a>(mut data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    while data.len() >= 8 {
+        let size = read_u32(data, 0)?;
+        let (header_len, size) = match size {
+            0 => (8, data.len() as u64),
+            1 => (16, read_u64(data, 8)?),
+            size => (8, u64::from(size)),
+        };
+        let size = usize::try_from(size).ok()?;
+        if size < header_len || size > data.len() {
+            return None;
+        }
+        if &data[4..8] == box_type {
+            return Some(&data[header_len..size]);
+        }
+        data = &data[size..];
+    }
+    None
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(box_type: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut data = u32::try_from(contents.len() + 8)
+            .unwrap()
+            .to_be_bytes()
+            .to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(contents);
+        data
+    }
+
+    fn mvhd_v0(timescale: u32, duration: u32) -> Vec<u8> {
+        let mut contents = vec![0; 4 + 8];
+        contents.extend_from_slice(&timescale.to_be_bytes());
+        contents.extend_from_slice(&duration.to_be_bytes());
+        contents.extend_from_slice(&[0; 80]);
+        mp4_box(b"mvhd", &contents)
+    }
+
+    fn mvhd_v1(timescale: u32, duration: u64) -> Vec<u8> {
+        let mut contents = vec![1, 0, 0, 0];
+        contents.extend_from_slice(&[0; 16]);
+        contents.extend_from_slice(&timescale.to_be_bytes());
+        contents.extend_from_slice(&duration.to_be_bytes());
+        contents.extend_from_slice(&[0; 80]);
+        mp4_box(b"mvhd", &contents)
+    }
+
+    fn mp4(mvhd: &[u8]) -> Vec<u8> {
+        let mut data = mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso2mp41");
+        data.extend(mp4_box(b"moov", mvhd));
+        data.extend(mp4_box(b"mdat", &[0; 16]));
+        data
+    }
+
+    #[test]
+    fn reads_version_0_duration() {
+        let video = mp4(&mvhd_v0(1000, 61_500));
+        assert_eq!(mp4_duration(&video), Some(Duration::from_millis(61_500)));
+    }
+
+    #[test]
+    fn reads_version_1_duration() {
+        let video = mp4(&mvhd_v1(90_000, 90_000 * 5_000_000_000));
+        assert_eq!(
+            mp4_duration(&video),
+            Some(Duration::from_secs(5_000_000_000))
+        );
+    }
+
+    #[test]
+    fn finds_mvhd_after_other_boxes() {
+        let mut moov = mp4_box(b"udta", &[0; 12]);
+        moov.extend(mvhd_v0(600, 1_800));
+        let video = mp4(&moov);
+        assert_eq!(mp4_duration(&video), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn reads_64_bit_box_sizes() {
+        let mvhd = mvhd_v0(1, 42);
+        let mut moov = 1_u32.to_be_bytes().to_vec();
+        moov.extend_from_slice(b"moov");
+        moov.extend_from_slice(&(mvhd.len() as u64 + 16).to_be_bytes());
+        moov.extend(mvhd);
+        assert_eq!(mp4_duration(&moov), Some(Duration::from_secs(42)));
+    }
+
+    #[test]
+    fn reads_boxes_that_extend_to_the_end() {
+        let mut video = mp4_box(b"ftyp", b"isom");
+        video.extend_from_slice(&0_u32.to_be_bytes());
+        video.extend_from_slice(b"moov");
+        video.extend(mvhd_v0(1, 7));
+        assert_eq!(mp4_duration(&video), Some(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn rejects_unknown_durations() {
+        assert_eq!(mp4_duration(&mp4(&mvhd_v0(1000, u32::MAX))), None);
+        assert_eq!(mp4_duration(&mp4(&mvhd_v1(1000, u64::MAX))), None);
+        assert_eq!(mp4_duration(&mp4(&mvhd_v0(0, 1000))), None);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut mvhd = mvhd_v0(1000, 1000);
+        mvhd[8] = 2;
+        assert_eq!(mp4_duration(&mp4(&mvhd)), None);
+    }
+
+    #[test]
+    fn rejects_truncated_boxes() {
+        let video = mp4(&mvhd_v0(1000, 61_500));
+        let moov_end = video.len() - 24;
+        // Cut inside the mvhd box, so the moov box claims more data than there is.
+        assert_eq!(mp4_duration(&video[..moov_end - 90]), None);
+        // Cut inside a box header.
+        assert_eq!(mp4_duration(&video[..4]), None);
+        assert_eq!(mp4_duration(&[]), None);
+    }
+
+    #[test]
+    fn rejects_mvhd_boxes_too_short_for_their_fields() {
+        let video = mp4(&mp4_box(b"mvhd", &[0; 16]));
+        assert_eq!(mp4_duration(&video), None);
+        let video = mp4(&mp4_box(b"mvhd", &[1; 28]));
+        assert_eq!(mp4_duration(&video), None);
+    }
+
+    #[test]
+    fn rejects_invalid_box_sizes() {
+        // A box smaller than its own header.
+        let mut video = 4_u32.to_be_bytes().to_vec();
+        video.extend_from_slice(b"moov");
+        video.extend(mvhd_v0(1, 1));
+        assert_eq!(mp4_duration(&video), None);
+    }
+
+    #[test]
+    fn rejects_videos_without_moov() {
+        let video = mp4_box(b"ftyp", b"isom");
+        assert_eq!(mp4_duration(&video), None);
+    }
+}